@@ -0,0 +1,98 @@
+//! Pointer cursor images.
+//!
+//! A theme is a directory of PNGs under `icons/<theme>/cursors/` plus a
+//! `cursors.theme` manifest naming each image and its hotspot, the pixel that
+//! sits exactly under the pointer. Cursors are looked up by their CSS
+//! `cursor` keyword, as in [`CursorShape::name`]; names the theme lacks fall
+//! back to `default`.
+
+use crate::common::file;
+use crate::render::util::image::Image;
+use libprotocol::{CursorShape, ScreenSize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Theme the compositor loads at startup.
+pub const DEFAULT_THEME: &str = "Default";
+
+static NEXT_CURSOR_ID: AtomicU64 = AtomicU64::new(0);
+
+pub struct Cursor {
+    id: u64,
+    image: Image,
+    hotspot_x: ScreenSize,
+    hotspot_y: ScreenSize,
+}
+
+impl Cursor {
+    pub fn new(image: Image, hotspot_x: ScreenSize, hotspot_y: ScreenSize) -> Self {
+        Self {
+            id: NEXT_CURSOR_ID.fetch_add(1, Ordering::Relaxed),
+            image,
+            hotspot_x,
+            hotspot_y,
+        }
+    }
+
+    /// Unique to this cursor, so renderers can tell when the image changes.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn hotspot(&self) -> (ScreenSize, ScreenSize) {
+        (self.hotspot_x, self.hotspot_y)
+    }
+}
+
+/// What a client asked to show over its window.
+pub enum ClientCursor {
+    Shape(CursorShape),
+    Image(Cursor),
+}
+
+pub struct CursorTheme {
+    cursors: HashMap<String, Cursor>,
+}
+
+impl CursorTheme {
+    /// Loads a theme from the shared data directory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the manifest or an image it names cannot be read, if a line
+    /// is malformed, or if there is no `default` cursor.
+    pub fn load(theme: &str) -> Self {
+        let dir = format!("icons/{}/cursors", theme);
+        let manifest = file::read_from_usr_share(&format!("{}/cursors.theme", dir));
+        let mut cursors = HashMap::new();
+        for (number, line) in manifest.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, image, x, y] = fields[..] else {
+                panic!("{}/cursors.theme:{}: expected `<name> <image> <x> <y>`", dir, number + 1);
+            };
+            let (Ok(x), Ok(y)) = (x.parse(), y.parse()) else {
+                panic!("{}/cursors.theme:{}: hotspot is not a number", dir, number + 1);
+            };
+            let image = file::read_image_from_usr_share(&format!("{}/{}", dir, image));
+            cursors.insert(name.to_string(), Cursor::new(image, x, y));
+        }
+        let default = CursorShape::Default.name();
+        assert!(cursors.contains_key(default), "cursor theme {} has no `{}` cursor", theme, default);
+        Self { cursors }
+    }
+
+    /// The cursor for `shape`, or the default one if the theme has none.
+    pub fn get(&self, shape: CursorShape) -> &Cursor {
+        self.cursors
+            .get(shape.name())
+            .unwrap_or_else(|| &self.cursors[CursorShape::Default.name()])
+    }
+}
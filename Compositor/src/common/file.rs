@@ -1,7 +1,9 @@
 use std::path::{Path, PathBuf};
-use std::fs;
-use std::io::Cursor;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor};
 use png::Decoder;
+use crate::render::util::image::Image;
+use libprotocol::ScreenSize;
 
 fn get_usr_share_path() -> PathBuf {
     #[cfg(debug_assertions)]
@@ -33,12 +35,34 @@ pub fn read_from_usr_share_to_vec(relative_path: &str) -> Vec<u8> {
         .unwrap_or_else(|err| panic!("Failed to read file {}: {}", file_path.display(), err))
 }
 
-fn extract_rgba(png_data: &[u8]) -> Vec<u8> {
+/// Loads an 8-bit RGBA PNG, such as an icon, from the shared data directory.
+pub fn read_image_from_usr_share(relative_path: &str) -> Image {
+    let png_data = read_from_usr_share_to_vec(relative_path);
+    extract_rgba(&png_data)
+}
+
+fn extract_rgba(png_data: &[u8]) -> Image {
     // Decode the PNG data
     let decoder = Decoder::new(Cursor::new(png_data));
     let mut reader = decoder.read_info().expect("Failed to read PNG info");
     let mut buf = vec![0; reader.output_buffer_size()];
     let info = reader.next_frame(&mut buf).expect("Failed to decode PNG frame");
+    assert_eq!(
+        (info.color_type, info.bit_depth),
+        (png::ColorType::Rgba, png::BitDepth::Eight),
+        "Only 8-bit RGBA PNGs are supported"
+    );
     buf.truncate(info.buffer_size());
-    buf
-}
\ No newline at end of file
+    Image::from_rgba(info.width as ScreenSize, info.height as ScreenSize, buf)
+}
+
+/// Saves an image as an 8-bit RGBA PNG.
+pub fn write_png(path: &Path, image: &Image) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width() as u32, image.height() as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(image.pixels()).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
@@ -1,2 +1,3 @@
 pub mod mouse;
 pub mod file;
+pub mod cursor;
@@ -1,8 +1,43 @@
+use crate::render::Renderer;
 use crate::window::display_manager::DisplayServer;
 use std::sync::atomic::{AtomicBool};
 use std::sync::{Arc, RwLock};
 use glfw::Context;
-use libprotocol::ScreenSize;
+use libprotocol::{KeyCode, Modifiers, ScreenSize};
+
+fn translate_modifiers(modifiers: glfw::Modifiers) -> Modifiers {
+    Modifiers {
+        shift: modifiers.contains(glfw::Modifiers::Shift),
+        ctrl: modifiers.contains(glfw::Modifiers::Control),
+        alt: modifiers.contains(glfw::Modifiers::Alt),
+        logo: modifiers.contains(glfw::Modifiers::Super),
+    }
+}
+
+fn translate_key(key: glfw::Key, scancode: glfw::Scancode, modifiers: glfw::Modifiers) -> Option<KeyCode> {
+    use glfw::Key;
+    Some(match key {
+        Key::Backspace => KeyCode::Backspace,
+        Key::Tab => KeyCode::Tab,
+        Key::Enter | Key::KpEnter => KeyCode::Enter,
+        Key::Escape => KeyCode::Escape,
+        Key::Space => KeyCode::Space,
+        Key::Delete => KeyCode::Delete,
+        _ if (Key::F1..=Key::F25).contains(&key) => {
+            KeyCode::Function((key as i32 - Key::F1 as i32 + 1) as u8)
+        }
+        _ => {
+            // Printable keys, named by the active keyboard layout
+            let name = glfw::get_key_name(Some(key), Some(scancode))?;
+            let character = name.chars().next()?;
+            if modifiers.contains(glfw::Modifiers::Shift) {
+                KeyCode::Character(character.to_uppercase().next()?)
+            } else {
+                KeyCode::Character(character)
+            }
+        }
+    })
+}
 
 pub fn start_screen(dm_server: Arc<RwLock<DisplayServer>>, shutdown: Arc<AtomicBool>) {
     #[cfg(debug_assertions)]
@@ -36,7 +71,8 @@ pub fn start_screen(dm_server: Arc<RwLock<DisplayServer>>, shutdown: Arc<AtomicB
         let mut dm = dm_server
             .write()
             .expect("Failed to get dm_server write lock");
-        dm.setup_renderer(width as ScreenSize, height as ScreenSize);
+        let (width, height) = (width as ScreenSize, height as ScreenSize);
+        dm.setup_renderer(Box::new(Renderer::new(width, height)), width, height);
     }
     window.set_all_polling(true);
     let target_frame_time = std::time::Duration::from_secs(1) / 60;
@@ -52,6 +88,18 @@ pub fn start_screen(dm_server: Arc<RwLock<DisplayServer>>, shutdown: Arc<AtomicB
                     shutdown.store(true, std::sync::atomic::Ordering::Relaxed);
                     window.set_should_close(true);
                 }
+                glfw::WindowEvent::Key(key, scancode, action, modifiers) => {
+                    if let Some(key) = translate_key(key, scancode, modifiers) {
+                        let mut dm = dm_server
+                            .write()
+                            .expect("Failed to acquire write lock on display server");
+                        dm.update_key_state(
+                            key,
+                            translate_modifiers(modifiers),
+                            action != glfw::Action::Release,
+                        );
+                    }
+                }
                 glfw::WindowEvent::CursorPos(x, y) => {
                     let mut dm = dm_server.write().expect("Failed to read display server");
                     // Calculate relative position
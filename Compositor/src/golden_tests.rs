@@ -0,0 +1,679 @@
+//! Golden-image tests for what the compositor draws.
+//!
+//! Every scene drives a `DisplayServer` through a short script and compares
+//! the composed frame against `tests/golden/<scene>.png`, once with the GL
+//! renderer in a surfaceless EGL context and once with `SoftwareRenderer`.
+//! Channels may differ by up to `TOLERANCE`; anything more fails the test and
+//! writes `<scene>.<backend>.actual.png` and `<scene>.<backend>.diff.png`
+//! next to the reference, with the offending pixels in red.
+//!
+//! Run with `PRISM_UPDATE_GOLDEN=1` to rewrite the references from the GL
+//! renderer after an intended change in rendering.
+//!
+//! Without a usable EGL driver the GL pass is skipped with a note on stderr
+//! and only the software renderer is checked.
+
+use crate::common::cursor::{ClientCursor, Cursor};
+use crate::common::file::write_png;
+use crate::render::api::egl::{EglContext, EglError};
+use crate::render::software::SoftwareRenderer;
+use crate::render::{RenderBackend, Renderer};
+use crate::render::util::image::Image;
+use crate::render::util::rect::{Rect, Size};
+use crate::render::wallpaper::Wallpaper;
+use crate::window::decoration::TitleButton;
+use crate::window::display_manager::DisplayServer;
+use crate::window::window::{DEFAULT_MIN_SIZE, TITLE_BAR_HEIGHT, Window};
+use libprotocol::{BackgroundFill, CursorShape, ScreenSize, WallpaperMode};
+use libprotocol::shm::BYTES_PER_PIXEL;
+use std::fs::File;
+use std::path::PathBuf;
+
+const SCREEN_WIDTH: ScreenSize = 320;
+const SCREEN_HEIGHT: ScreenSize = 240;
+/// Largest per-channel difference still counted as a match.
+const TOLERANCE: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Gl,
+    Software,
+}
+
+impl Backend {
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Gl => "gl",
+            Backend::Software => "software",
+        }
+    }
+}
+
+struct Scene {
+    backend: Backend,
+    // Declared before the context so its GL objects go first
+    dm: DisplayServer,
+    _egl: Option<EglContext>,
+    /// Where the pointer is; `DisplayServer` only takes relative motion.
+    mouse: (ScreenSize, ScreenSize),
+}
+
+impl Scene {
+    /// Fails only for the GL backend, when no EGL context can be made.
+    fn new(backend: Backend) -> Result<Self, EglError> {
+        let (egl, renderer): (_, Box<dyn RenderBackend>) = match backend {
+            Backend::Gl => (
+                Some(EglContext::new()?),
+                Box::new(Renderer::new_offscreen(SCREEN_WIDTH, SCREEN_HEIGHT)),
+            ),
+            Backend::Software => (None, Box::new(SoftwareRenderer::new(SCREEN_WIDTH, SCREEN_HEIGHT))),
+        };
+        let mut dm = DisplayServer::new();
+        dm.setup_renderer(renderer, SCREEN_WIDTH, SCREEN_HEIGHT);
+        Ok(Self {
+            backend,
+            dm,
+            _egl: egl,
+            mouse: (0, 0),
+        })
+    }
+
+    fn add_window(&mut self, window: Window) -> u64 {
+        self.dm.add_window(window, 1)
+    }
+
+    fn move_mouse_to(&mut self, x: ScreenSize, y: ScreenSize) {
+        self.dm.update_mouse_pos(x - self.mouse.0, y - self.mouse.1);
+        self.mouse = (x, y);
+        self.dm.tick();
+    }
+
+    /// Presses the left button at `from`, moves to `to` and releases.
+    ///
+    /// Moves a few pixels per frame like a real pointer, since a grab is
+    /// hit-tested against where the pointer ends up each frame.
+    fn drag(&mut self, from: (ScreenSize, ScreenSize), to: (ScreenSize, ScreenSize)) {
+        const STEP: ScreenSize = 4;
+        self.move_mouse_to(from.0, from.1);
+        self.dm.update_button_state(0, true);
+        while self.mouse != to {
+            let x = self.mouse.0 + (to.0 - self.mouse.0).clamp(-STEP, STEP);
+            let y = self.mouse.1 + (to.1 - self.mouse.1).clamp(-STEP, STEP);
+            self.move_mouse_to(x, y);
+        }
+        self.dm.update_button_state(0, false);
+    }
+
+    fn click(&mut self, x: ScreenSize, y: ScreenSize) {
+        self.move_mouse_to(x, y);
+        self.dm.update_button_state(0, true);
+        self.dm.tick();
+        self.dm.update_button_state(0, false);
+    }
+
+    /// Centre of a title bar button of a window at `rect`.
+    fn button_centre(rect: &Rect, button: TitleButton) -> (ScreenSize, ScreenSize) {
+        let rect = button.rect(rect);
+        (
+            rect.position.x + rect.size.width / 2,
+            rect.position.y + rect.size.height / 2,
+        )
+    }
+
+    fn assert_matches(mut self, name: &str) {
+        self.dm.tick();
+        let frame = self.dm.read_frame().expect("renderer is set up");
+        assert_golden(name, self.backend, &frame);
+    }
+}
+
+/// Plays `script` on a fresh scene per backend and checks the final frame.
+fn check_scene(name: &str, script: impl Fn(&mut Scene)) {
+    for backend in [Backend::Gl, Backend::Software] {
+        let mut scene = match Scene::new(backend) {
+            Ok(scene) => scene,
+            Err(e) => {
+                // References are written from the GL pass, so don't let an
+                // update quietly do nothing
+                assert!(
+                    std::env::var_os("PRISM_UPDATE_GOLDEN").is_none(),
+                    "PRISM_UPDATE_GOLDEN needs EGL: {}",
+                    e
+                );
+                eprintln!("{}: skipping the {} pass, no EGL: {}", name, backend.name(), e);
+                continue;
+            }
+        };
+        script(&mut scene);
+        scene.assert_matches(name);
+    }
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn read_png(path: &PathBuf) -> Image {
+    let file = File::open(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let mut reader = png::Decoder::new(file).read_info().expect("reference is a valid PNG");
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).expect("reference is a valid PNG");
+    assert_eq!(
+        (info.color_type, info.bit_depth),
+        (png::ColorType::Rgba, png::BitDepth::Eight),
+        "{} is not 8-bit RGBA",
+        path.display()
+    );
+    pixels.truncate(info.buffer_size());
+    Image::from_rgba(info.width as ScreenSize, info.height as ScreenSize, pixels)
+}
+
+/// Compares `actual` with the reference, writing a diff image on mismatch.
+fn assert_golden(name: &str, backend: Backend, actual: &Image) {
+    let dir = golden_dir();
+    let reference = dir.join(format!("{}.png", name));
+    if std::env::var_os("PRISM_UPDATE_GOLDEN").is_some() && backend == Backend::Gl {
+        write_png(&reference, actual).expect("failed to write reference");
+        return;
+    }
+    let expected = read_png(&reference);
+    assert_eq!(
+        (actual.width(), actual.height()),
+        (expected.width(), expected.height()),
+        "{} ({}) has a different size than its reference",
+        name,
+        backend.name()
+    );
+
+    let mut diff = Image::new(actual.width(), actual.height());
+    let mut mismatched = 0;
+    let mut worst = 0;
+    for ((a, e), d) in actual
+        .pixels()
+        .chunks_exact(BYTES_PER_PIXEL)
+        .zip(expected.pixels().chunks_exact(BYTES_PER_PIXEL))
+        .zip(diff.pixels_mut().chunks_exact_mut(BYTES_PER_PIXEL))
+    {
+        let delta = a.iter().zip(e).map(|(a, e)| a.abs_diff(*e)).max().unwrap_or(0);
+        worst = worst.max(delta);
+        if delta > TOLERANCE {
+            mismatched += 1;
+            d.copy_from_slice(&[255, 0, 0, 255]);
+        } else {
+            // Faded copy of the reference for orientation
+            let grey = ((e[0] as u16 + e[1] as u16 + e[2] as u16) / 3 / 4) as u8;
+            d.copy_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+    if mismatched > 0 {
+        let actual_path = dir.join(format!("{}.{}.actual.png", name, backend.name()));
+        let diff_path = dir.join(format!("{}.{}.diff.png", name, backend.name()));
+        write_png(&actual_path, actual).expect("failed to write actual frame");
+        write_png(&diff_path, &diff).expect("failed to write diff image");
+        panic!(
+            "{} ({}): {} pixels differ by up to {} (tolerance {}), see {}",
+            name,
+            backend.name(),
+            mismatched,
+            worst,
+            TOLERANCE,
+            diff_path.display()
+        );
+    }
+}
+
+/// A 2x2 checkerboard of 8 pixel cells in opaque red and translucent blue.
+fn checkerboard(width: ScreenSize, height: ScreenSize) -> Image {
+    let mut image = Image::new(width, height);
+    for (i, pixel) in image.pixels_mut().chunks_exact_mut(BYTES_PER_PIXEL).enumerate() {
+        let (x, y) = (i as ScreenSize % width, i as ScreenSize / width);
+        let colour = if (x / 8 + y / 8) % 2 == 0 {
+            [200, 40, 40, 255]
+        } else {
+            [40, 40, 200, 128]
+        };
+        pixel.copy_from_slice(&colour);
+    }
+    image
+}
+
+const GRADIENT: BackgroundFill = BackgroundFill::VerticalGradient {
+    top: [30, 60, 110],
+    bottom: [150, 90, 40],
+};
+
+/// Shows a portrait checkerboard in `mode` over `GRADIENT`, so the fill
+/// shows through wherever the image leaves gaps or is translucent.
+fn check_wallpaper_mode(name: &str, mode: WallpaperMode) {
+    check_scene(name, |scene| {
+        scene
+            .dm
+            .set_wallpaper(Wallpaper::with_image(checkerboard(64, 96), mode, GRADIENT));
+    });
+}
+
+#[test]
+fn empty_desktop() {
+    check_scene("empty_desktop", |_| {});
+}
+
+#[test]
+fn titled_window() {
+    check_scene("titled_window", |scene| {
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+    });
+}
+
+#[test]
+fn untitled_window_with_content() {
+    check_scene("untitled_window_with_content", |scene| {
+        let window_id = scene.add_window(Window::new_non_titled(Rect::new(60, 40, 120, 90)));
+        scene.dm.update_window_frame_buffer(&window_id, checkerboard(100, 90));
+    });
+}
+
+#[test]
+fn window_moved_by_title_bar() {
+    check_scene("window_moved_by_title_bar", |scene| {
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.drag((100, 30 + TITLE_BAR_HEIGHT / 2), (160, 80 + TITLE_BAR_HEIGHT / 2));
+    });
+}
+
+#[test]
+fn maximized_window() {
+    check_scene("maximized_window", |scene| {
+        let mut window = Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100));
+        window.maximize();
+        scene.add_window(window);
+    });
+}
+
+#[test]
+fn minimized_window() {
+    check_scene("minimized_window", |scene| {
+        let mut window = Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100));
+        window.minimize();
+        scene.add_window(window);
+    });
+}
+
+#[test]
+fn cursor_above_window() {
+    check_scene("cursor_above_window", |scene| {
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.move_mouse_to(120, 90);
+    });
+}
+
+#[test]
+fn title_button_hovered() {
+    check_scene("title_button_hovered", |scene| {
+        let rect = Rect::new(40, 30, 160, 100);
+        scene.add_window(Window::new_titled("Title".to_string(), rect));
+        let (x, y) = Scene::button_centre(&rect, TitleButton::Close);
+        scene.move_mouse_to(x, y);
+    });
+}
+
+#[test]
+fn title_button_pressed() {
+    check_scene("title_button_pressed", |scene| {
+        let rect = Rect::new(40, 30, 160, 100);
+        scene.add_window(Window::new_titled("Title".to_string(), rect));
+        let (x, y) = Scene::button_centre(&rect, TitleButton::Maximize);
+        scene.move_mouse_to(x, y);
+        scene.dm.update_button_state(0, true);
+    });
+}
+
+#[test]
+fn window_maximized_by_button() {
+    check_scene("window_maximized_by_button", |scene| {
+        let rect = Rect::new(40, 30, 160, 100);
+        scene.add_window(Window::new_titled("Title".to_string(), rect));
+        let (x, y) = Scene::button_centre(&rect, TitleButton::Maximize);
+        scene.click(x, y);
+    });
+}
+
+#[test]
+fn window_painted_after_close_button() {
+    check_scene("window_painted_after_close_button", |scene| {
+        let rect = Rect::new(40, 30, 160, 100);
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), rect));
+        let (x, y) = Scene::button_centre(&rect, TitleButton::Close);
+        scene.click(x, y);
+        // A frame the client sent before it saw `Closed`
+        scene.dm.update_window_frame_buffer(&window_id, checkerboard(160, 100));
+    });
+}
+
+#[test]
+fn window_closed_by_client_after_close_button() {
+    check_scene("window_closed_by_client_after_close_button", |scene| {
+        let rect = Rect::new(40, 30, 160, 100);
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), rect));
+        let (x, y) = Scene::button_centre(&rect, TitleButton::Close);
+        scene.click(x, y);
+        scene.dm.remove_window(&window_id);
+    });
+}
+
+#[test]
+fn long_title_ellipsized() {
+    check_scene("long_title_ellipsized", |scene| {
+        let title = "A title far too long to fit between the icon and the buttons";
+        scene.add_window(Window::new_titled(title.to_string(), Rect::new(40, 30, 160, 100)));
+    });
+}
+
+#[test]
+fn cursor_on_title_bar() {
+    check_scene("cursor_on_title_bar", |scene| {
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.move_mouse_to(100, 30 + TITLE_BAR_HEIGHT / 2);
+    });
+}
+
+#[test]
+fn cursor_on_right_edge() {
+    check_scene("cursor_on_right_edge", |scene| {
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.move_mouse_to(205, 90);
+    });
+}
+
+#[test]
+fn client_cursor_shape() {
+    check_scene("client_cursor_shape", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.set_window_cursor(&window_id, ClientCursor::Shape(CursorShape::Text));
+        scene.move_mouse_to(120, 90);
+    });
+}
+
+#[test]
+fn client_cursor_image() {
+    check_scene("client_cursor_image", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        let cursor = Cursor::new(checkerboard(16, 16), 8, 8);
+        scene.dm.set_window_cursor(&window_id, ClientCursor::Image(cursor));
+        scene.move_mouse_to(120, 90);
+    });
+}
+
+#[test]
+fn client_cursor_left_window() {
+    check_scene("client_cursor_left_window", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.set_window_cursor(&window_id, ClientCursor::Shape(CursorShape::Text));
+        scene.move_mouse_to(120, 90);
+        scene.move_mouse_to(260, 200);
+    });
+}
+
+#[test]
+fn wallpaper_gradient() {
+    check_scene("wallpaper_gradient", |scene| {
+        scene.dm.set_wallpaper(Wallpaper::new(GRADIENT));
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+    });
+}
+
+#[test]
+fn wallpaper_fill() {
+    check_wallpaper_mode("wallpaper_fill", WallpaperMode::Fill);
+}
+
+#[test]
+fn wallpaper_fit() {
+    check_wallpaper_mode("wallpaper_fit", WallpaperMode::Fit);
+}
+
+#[test]
+fn wallpaper_stretch() {
+    check_wallpaper_mode("wallpaper_stretch", WallpaperMode::Stretch);
+}
+
+#[test]
+fn wallpaper_center() {
+    check_wallpaper_mode("wallpaper_center", WallpaperMode::Center);
+}
+
+#[test]
+fn wallpaper_tile() {
+    check_wallpaper_mode("wallpaper_tile", WallpaperMode::Tile);
+}
+
+#[test]
+fn wallpaper_loaded_from_png() {
+    let path = std::env::temp_dir().join(format!("prism-wallpaper-{}.png", std::process::id()));
+    write_png(&path, &checkerboard(64, 96)).expect("failed to write wallpaper");
+    check_scene("wallpaper_loaded_from_png", |scene| {
+        let wallpaper = Wallpaper::load(&path, WallpaperMode::Fit, BackgroundFill::Solid([20, 90, 60]))
+            .expect("wallpaper decodes");
+        scene.dm.set_wallpaper(wallpaper);
+    });
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Two overlapping titled windows, "Back" added before "Front".
+fn add_overlapping_windows(scene: &mut Scene) -> (u64, u64) {
+    let back = scene.add_window(Window::new_titled("Back".to_string(), Rect::new(30, 30, 150, 100)));
+    scene.dm.update_window_frame_buffer(&back, checkerboard(150, 100));
+    let front = scene.add_window(Window::new_titled("Front".to_string(), Rect::new(110, 80, 150, 100)));
+    (back, front)
+}
+
+#[test]
+fn new_window_stacked_on_top() {
+    check_scene("new_window_stacked_on_top", |scene| {
+        add_overlapping_windows(scene);
+    });
+}
+
+#[test]
+fn click_raises_window() {
+    check_scene("click_raises_window", |scene| {
+        add_overlapping_windows(scene);
+        // Client area of "Back" that "Front" does not cover
+        scene.click(60, 120);
+    });
+}
+
+#[test]
+fn drag_moves_raised_window() {
+    check_scene("drag_moves_raised_window", |scene| {
+        add_overlapping_windows(scene);
+        scene.drag((60, 30 + TITLE_BAR_HEIGHT / 2), (40, 20 + TITLE_BAR_HEIGHT / 2));
+    });
+}
+
+#[test]
+fn resized_from_top_left_corner() {
+    check_scene("resized_from_top_left_corner", |scene| {
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(100, 80, 160, 100)));
+        // The bottom-right corner stays put
+        scene.drag((99, 79), (60, 50));
+    });
+}
+
+#[test]
+fn resized_from_left_edge_to_minimum() {
+    check_scene("resized_from_left_edge_to_minimum", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 200, 100)));
+        scene.dm.set_window_size_limits(
+            &window_id,
+            Size {
+                width: 150,
+                height: 50,
+            },
+            None,
+        );
+        scene.drag((38, 100), (200, 100));
+    });
+}
+
+#[test]
+fn resized_from_bottom_edge_to_maximum() {
+    check_scene("resized_from_bottom_edge_to_maximum", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 80)));
+        scene.dm.set_window_size_limits(
+            &window_id,
+            DEFAULT_MIN_SIZE,
+            Some(Size {
+                width: 160,
+                height: 120,
+            }),
+        );
+        scene.drag((120, 146), (120, 220));
+    });
+}
+
+#[test]
+fn cursor_on_top_right_corner() {
+    check_scene("cursor_on_top_right_corner", |scene| {
+        scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.move_mouse_to(207, 29);
+    });
+}
+
+#[test]
+fn window_maximized_by_request() {
+    check_scene("window_maximized_by_request", |scene| {
+        let (back, _) = add_overlapping_windows(scene);
+        scene.dm.maximize_window(&back);
+    });
+}
+
+#[test]
+fn unmaximized_window_goes_back() {
+    // Ends up exactly like a window that was never maximized
+    check_scene("titled_window", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.maximize_window(&window_id);
+        scene.dm.unmaximize_window(&window_id);
+    });
+}
+
+#[test]
+fn window_minimized_by_request() {
+    check_scene("window_minimized_by_request", |scene| {
+        let (_, front) = add_overlapping_windows(scene);
+        scene.dm.minimize_window(&front);
+    });
+}
+
+#[test]
+fn fullscreen_window_covers_screen() {
+    check_scene("fullscreen_window_covers_screen", |scene| {
+        let (back, _) = add_overlapping_windows(scene);
+        scene.dm.fullscreen_window(&back);
+        // Repainted at the size it was configured to
+        scene.dm.update_window_frame_buffer(&back, checkerboard(SCREEN_WIDTH, SCREEN_HEIGHT));
+    });
+}
+
+#[test]
+fn unfullscreen_window_stays_maximized() {
+    check_scene("window_maximized_by_request", |scene| {
+        let (back, _) = add_overlapping_windows(scene);
+        scene.dm.maximize_window(&back);
+        scene.dm.fullscreen_window(&back);
+        scene.dm.unfullscreen_window(&back);
+    });
+}
+
+/// A window whose client acknowledges configures, painted at 160x100 and
+/// then grown to 200x140 by its size limits. That sends its first
+/// configure, with serial 1.
+fn add_window_being_resized(scene: &mut Scene) -> u64 {
+    let mut window = Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100));
+    window.set_acks_configure(true);
+    let window_id = scene.add_window(window);
+    scene.dm.update_window_frame_buffer(&window_id, checkerboard(160, 100));
+    let min = Size {
+        width: 200,
+        height: 140,
+    };
+    scene.dm.set_window_size_limits(&window_id, min, None);
+    window_id
+}
+
+#[test]
+fn old_frame_kept_until_configure_acked() {
+    check_scene("old_frame_kept_until_configure_acked", |scene| {
+        let window_id = add_window_being_resized(scene);
+        // Painted before the client saw the configure, so it is not shown
+        scene.dm.update_window_frame_buffer(&window_id, Image::new(160, 100));
+    });
+}
+
+#[test]
+fn frame_at_acked_size_shown() {
+    check_scene("frame_at_acked_size_shown", |scene| {
+        let window_id = add_window_being_resized(scene);
+        assert!(!scene.dm.ack_window_configure(&window_id, 2));
+        assert!(scene.dm.ack_window_configure(&window_id, 1));
+        // Still the old size, so still not shown
+        scene.dm.update_window_frame_buffer(&window_id, Image::new(160, 100));
+        scene.dm.update_window_frame_buffer(&window_id, checkerboard(200, 140));
+    });
+}
+
+#[test]
+fn window_title_and_icon_updated() {
+    check_scene("window_title_and_icon_updated", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.set_window_title(&window_id, Some("Renamed".to_string()));
+        // Scaled down to the icon slot
+        scene.dm.set_window_icon(&window_id, Some(checkerboard(64, 64)));
+    });
+}
+
+#[test]
+fn window_icon_removed() {
+    check_scene("titled_window", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.set_window_icon(&window_id, Some(checkerboard(64, 64)));
+        scene.dm.set_window_icon(&window_id, None);
+    });
+}
+
+#[test]
+fn app_id_shown_without_title() {
+    check_scene("app_id_shown_without_title", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.set_window_title(&window_id, None);
+        scene.dm.set_window_app_id(&window_id, Some("org.example.App".to_string()));
+    });
+}
+
+#[test]
+fn title_shown_over_app_id() {
+    check_scene("titled_window", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.set_window_app_id(&window_id, Some("org.example.App".to_string()));
+    });
+}
+
+#[test]
+fn window_decorations_removed() {
+    check_scene("window_decorations_removed", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.update_window_frame_buffer(&window_id, checkerboard(160, 100));
+        // The client area stays where it was
+        scene.dm.set_window_decorations(&window_id, false);
+    });
+}
+
+#[test]
+fn non_resizable_window_ignores_edge_drag() {
+    check_scene("non_resizable_window_ignores_edge_drag", |scene| {
+        let window_id = scene.add_window(Window::new_titled("Title".to_string(), Rect::new(40, 30, 160, 100)));
+        scene.dm.set_window_resizable(&window_id, false);
+        scene.drag((205, 90), (250, 120));
+        scene.move_mouse_to(205, 90);
+    });
+}
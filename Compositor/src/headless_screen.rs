@@ -0,0 +1,167 @@
+//! Runs the compositor without any display.
+//!
+//! Frames are composed at a fixed virtual resolution, either on the CPU or
+//! by the GL renderer in a surfaceless EGL context, and can be written to PNG
+//! every N frames or whenever the process receives `SIGUSR1`.
+
+use crate::common;
+use crate::render::api::egl::EglContext;
+use crate::render::software::SoftwareRenderer;
+use crate::render::{RenderBackend, Renderer};
+use crate::window::display_manager::DisplayServer;
+use libprotocol::ScreenSize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadlessRenderer {
+    /// `SoftwareRenderer`, no GL at all.
+    Software,
+    /// The GL renderer with its shaders, in a context from `EglContext`.
+    Gl,
+}
+
+pub struct HeadlessConfig {
+    pub renderer: HeadlessRenderer,
+    pub width: ScreenSize,
+    pub height: ScreenSize,
+    /// Where frame dumps are written.
+    pub dump_dir: PathBuf,
+    /// Dump every N-th frame; `None` only dumps on `SIGUSR1`.
+    pub dump_every: Option<u64>,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            renderer: HeadlessRenderer::Software,
+            width: 1920,
+            height: 1080,
+            dump_dir: PathBuf::from("."),
+            dump_every: None,
+        }
+    }
+}
+
+impl HeadlessConfig {
+    /// Parses the command line, returning `None` unless `--headless` is given.
+    ///
+    /// Accepts `--renderer software|gl`, `--size WxH`, `--dump-dir DIR` and
+    /// `--dump-every N`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut headless = false;
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--renderer" => {
+                    config.renderer = match value("--renderer")?.as_str() {
+                        "software" => HeadlessRenderer::Software,
+                        "gl" => HeadlessRenderer::Gl,
+                        other => return Err(format!("unknown renderer {}", other)),
+                    }
+                }
+                "--size" => {
+                    let size = value("--size")?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .filter(|&(w, h): &(ScreenSize, ScreenSize)| w > 0 && h > 0)
+                        .ok_or(format!("invalid size {}, expected WIDTHxHEIGHT", size))?;
+                    config.width = width;
+                    config.height = height;
+                }
+                "--dump-dir" => config.dump_dir = PathBuf::from(value("--dump-dir")?),
+                "--dump-every" => {
+                    let every = value("--dump-every")?;
+                    config.dump_every = Some(
+                        every
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or(format!("invalid frame count {}", every))?,
+                    );
+                }
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+        Ok(headless.then_some(config))
+    }
+}
+
+pub fn start_screen(
+    dm_server: Arc<RwLock<DisplayServer>>,
+    shutdown: Arc<AtomicBool>,
+    config: HeadlessConfig,
+) {
+    let dump_requested = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&dump_requested))
+        .expect("failed to set up SIGUSR1 hook");
+    // Must outlive every GL object the renderer creates
+    let egl = match config.renderer {
+        HeadlessRenderer::Software => None,
+        HeadlessRenderer::Gl => match EglContext::new() {
+            Ok(egl) => Some(egl),
+            Err(e) => {
+                eprintln!("Failed to create EGL context: {}", e);
+                shutdown.store(true, Ordering::Relaxed);
+                return;
+            }
+        },
+    };
+    {
+        let mut dm = dm_server
+            .write()
+            .expect("Failed to get dm_server write lock");
+        let renderer: Box<dyn RenderBackend> = match config.renderer {
+            HeadlessRenderer::Software => {
+                Box::new(SoftwareRenderer::new(config.width, config.height))
+            }
+            HeadlessRenderer::Gl => Box::new(Renderer::new_offscreen(config.width, config.height)),
+        };
+        dm.setup_renderer(renderer, config.width, config.height);
+    }
+    println!(
+        "Running headless at {}x{}, dumping frames to {}",
+        config.width,
+        config.height,
+        config.dump_dir.display()
+    );
+
+    let target_frame_time = Duration::from_secs(1) / 60;
+    let mut frame_number: u64 = 0;
+    while !shutdown.load(Ordering::Relaxed) {
+        let start_time = Instant::now();
+        frame_number += 1;
+        let dump = dump_requested.swap(false, Ordering::Relaxed)
+            || config.dump_every.is_some_and(|every| frame_number.is_multiple_of(every));
+        let frame = {
+            let mut dm = dm_server
+                .write()
+                .expect("Failed to acquire write lock on display server");
+            dm.tick();
+            if dump { dm.read_frame() } else { None }
+        };
+        if let Some(frame) = frame {
+            let path = config.dump_dir.join(format!("frame-{:06}.png", frame_number));
+            match common::file::write_png(&path, &frame) {
+                Ok(()) => println!("Wrote {}", path.display()),
+                Err(e) => eprintln!("Failed to write {}: {}", path.display(), e),
+            }
+        }
+        let elapsed = start_time.elapsed();
+        if elapsed < target_frame_time {
+            std::thread::sleep(target_frame_time - elapsed);
+        }
+    }
+    if egl.is_some() {
+        dm_server
+            .write()
+            .expect("Failed to get dm_server write lock")
+            .cleanup();
+    }
+}
@@ -1,15 +1,27 @@
-#![feature(random)]
-
+use crate::common::cursor::{ClientCursor, Cursor};
+use crate::headless_screen::HeadlessConfig;
+use crate::net::error::UnixSocketError;
 use crate::net::{CompositorServer, PacketHandler};
+use crate::render::util::image::Image;
+use crate::render::util::rect::Size;
+use crate::render::wallpaper::{self, Wallpaper};
 use crate::window::display_manager::DisplayServer;
 use crate::window::window::Window;
-use std::collections::HashMap;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, RwLock};
-use libprotocol::Packet;
+use libprotocol::shm::ShmMapping;
+use libprotocol::{
+    BackgroundFill, Capabilities, ErrorCode, Event, MAX_CURSOR_SIZE, MAX_ICON_SIZE, MAX_SURFACE_SIZE,
+    MAX_TITLE_LENGTH, Negotiated, Packet, ScreenSize, SharedFd, WallpaperMode,
+};
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
 
 mod common;
 mod debug_screen;
+#[cfg(test)]
+mod golden_tests;
+mod headless_screen;
 mod math;
 mod net;
 mod release_screen;
@@ -18,87 +30,552 @@ mod window;
 
 struct Prism {
     pub display: Arc<RwLock<DisplayServer>>,
-    id_map: HashMap<u64, u64>,
+    wallpaper_decoder: WallpaperDecoder,
+}
+
+/// A client's wallpaper image waiting to be decoded.
+struct WallpaperRequest {
+    connection_id: u64,
+    file: File,
+    mode: WallpaperMode,
+    fill: BackgroundFill,
+    generation: u64,
+}
+
+/// Decodes client wallpapers one at a time on a thread of its own, since a
+/// large image would otherwise hold up every other client.
+///
+/// Only the latest request waits for the thread. A newer one replaces it,
+/// closing its file unread, so clients cannot pile up decodes.
+struct WallpaperDecoder {
+    pending: Arc<(Mutex<Option<WallpaperRequest>>, Condvar)>,
+    /// Counts `SetWallpaper` requests, so a slow decode cannot replace a
+    /// wallpaper set after it.
+    generation: Arc<AtomicU64>,
+    started: bool,
+}
+
+impl WallpaperDecoder {
+    fn new() -> Self {
+        Self {
+            pending: Arc::new((Mutex::new(None), Condvar::new())),
+            generation: Arc::new(AtomicU64::new(0)),
+            started: false,
+        }
+    }
+
+    /// Queues `file` for decoding in place of any request still waiting.
+    /// Decoding errors are sent to the client once they happen.
+    fn decode(
+        &mut self,
+        display: &Arc<RwLock<DisplayServer>>,
+        connection_id: u64,
+        file: File,
+        mode: WallpaperMode,
+        fill: BackgroundFill,
+    ) {
+        if !self.started {
+            self.started = self.spawn(Arc::clone(display));
+        }
+        let request = WallpaperRequest {
+            connection_id,
+            file,
+            mode,
+            fill,
+            generation: self.supersede(),
+        };
+        let (pending, wake) = &*self.pending;
+        *pending.lock().unwrap() = Some(request);
+        wake.notify_one();
+    }
+
+    /// Drops the waiting request and makes the one being decoded stale.
+    /// Returns the generation of whatever wallpaper comes next.
+    fn supersede(&self) -> u64 {
+        self.pending.0.lock().unwrap().take();
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    fn spawn(&self, display: Arc<RwLock<DisplayServer>>) -> bool {
+        let pending = Arc::clone(&self.pending);
+        let generation = Arc::clone(&self.generation);
+        let spawned = thread::Builder::new().name("wallpaper-decoder".into()).spawn(move || {
+            loop {
+                let request = {
+                    let (pending, wake) = &*pending;
+                    let guard = wake.wait_while(pending.lock().unwrap(), |pending| pending.is_none());
+                    guard.unwrap().take().expect("woken with a request")
+                };
+                let wallpaper = Wallpaper::from_file(request.file, request.mode, request.fill);
+                let mut dm = display.write().unwrap();
+                match wallpaper {
+                    // Checked under the lock so a newer request always wins
+                    Ok(wallpaper) if generation.load(Ordering::SeqCst) == request.generation => {
+                        dm.set_wallpaper(wallpaper)
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if let Some(events) = dm.event_sender() {
+                            let error = Packet::Error {
+                                request: "SetWallpaper".to_string(),
+                                code: ErrorCode::InvalidImage,
+                                message: e.to_string(),
+                            };
+                            events.send(request.connection_id, error);
+                        }
+                    }
+                }
+            }
+        });
+        if let Err(e) = &spawned {
+            eprintln!("Failed to start wallpaper decoder: {}", e);
+        }
+        spawned.is_ok()
+    }
 }
 
 impl Prism {
     pub fn new() -> Self {
         Self {
             display: Arc::new(RwLock::new(DisplayServer::new())),
-            id_map: HashMap::new(),
+            wallpaper_decoder: WallpaperDecoder::new(),
         }
     }
+
+    /// Fails unless `window_id` names a window created by `connection_id`.
+    fn check_owner(&self, connection_id: u64, window_id: u64) -> net::Result<()> {
+        let dm = self.display.read().unwrap();
+        match dm.get_window_owner(window_id) {
+            Some(owner) if owner == connection_id => Ok(()),
+            Some(_) => Err(UnixSocketError::protocol(
+                ErrorCode::NotOwner,
+                format!("window {} belongs to another client", window_id),
+            )),
+            None if dm.used_to_own_window(connection_id, window_id) => {
+                Err(UnixSocketError::ClosedWindow(window_id))
+            }
+            None => Err(UnixSocketError::protocol(
+                ErrorCode::UnknownWindow,
+                format!("window {} does not exist", window_id),
+            )),
+        }
+    }
+
+    /// Fails if a title or app ID is longer than `MAX_TITLE_LENGTH`.
+    fn check_text(text: &Option<String>, what: &str) -> net::Result<()> {
+        match text {
+            Some(text) if text.len() > MAX_TITLE_LENGTH => Err(UnixSocketError::protocol(
+                ErrorCode::InvalidRequest,
+                format!("{} of {} bytes exceeds the {} byte limit", what, text.len(), MAX_TITLE_LENGTH),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_size(width: ScreenSize, height: ScreenSize) -> net::Result<()> {
+        Self::check_size_up_to(width, height, MAX_SURFACE_SIZE)
+    }
+
+    fn check_size_up_to(width: ScreenSize, height: ScreenSize, max: ScreenSize) -> net::Result<()> {
+        if (1..=max).contains(&width) && (1..=max).contains(&height) {
+            Ok(())
+        } else {
+            Err(UnixSocketError::protocol(
+                ErrorCode::InvalidSize,
+                format!("{}x{} is not a valid size", width, height),
+            ))
+        }
+    }
+
+    /// Copies `width * height` RGBA pixels out of a client's shared buffer,
+    /// so the client can reuse the buffer right away.
+    fn read_buffer(
+        buffer: &SharedFd,
+        width: ScreenSize,
+        height: ScreenSize,
+        request: &str,
+    ) -> net::Result<Image> {
+        let mapping = Self::map_buffer(buffer, width, height, request)?;
+        Ok(Image::from_rgba(width, height, mapping.as_slice().to_vec()))
+    }
+
+    /// Maps `width * height` RGBA pixels of a client's shared buffer.
+    fn map_buffer(
+        buffer: &SharedFd,
+        width: ScreenSize,
+        height: ScreenSize,
+        request: &str,
+    ) -> net::Result<ShmMapping> {
+        let fd = buffer.as_fd().ok_or_else(|| {
+            UnixSocketError::protocol(ErrorCode::InvalidBuffer, format!("{} without a buffer", request))
+        })?;
+        ShmMapping::map(fd, Image::byte_len(width, height))
+            .map_err(|e| UnixSocketError::protocol(ErrorCode::InvalidBuffer, e.to_string()))
+    }
 }
 
 impl PacketHandler for Prism {
-    fn handle_packet(&mut self, window_id: u64, packet: Packet) -> net::Result<Option<Packet>> {
+    fn handle_packet(
+        &mut self,
+        connection_id: u64,
+        negotiated: &Negotiated,
+        packet: Packet,
+    ) -> net::Result<Option<Packet>> {
         match packet {
             Packet::Create {
                 width,
                 height,
                 title,
             } => {
-                let window = if let Some(title) = title {
+                Self::check_size(width, height)?;
+                Self::check_text(&title, "title")?;
+                if !negotiated.supports(Capabilities::MULTI_WINDOW)
+                    && self.display.read().unwrap().has_windows(connection_id)
+                {
+                    // Its events could not say which window they are for
+                    return Err(UnixSocketError::protocol(
+                        ErrorCode::InvalidRequest,
+                        "client without multi-window support already has a window".to_string(),
+                    ));
+                }
+                let mut window = if let Some(title) = title {
                     let dm = self.display.read().unwrap();
                     Window::new_titled(title,dm.get_center(width, height))
                 } else {
                     let dm = self.display.read().unwrap();
                     Window::new_non_titled(dm.get_center(width, height))
                 };
+                window.set_acks_configure(
+                    negotiated.supports(Capabilities::WINDOW_STATES | Capabilities::CONFIGURE_ACK),
+                );
                 let mut dm = self.display.write().unwrap();
-                let id = dm.add_window(window);
-                self.id_map.insert(window_id,id);
+                let window_id = dm.add_window(window, connection_id);
                 return Ok(Option::from(Packet::CreateSuccess { window_id }));
             }
             /*Packet::RequestWindowPosition { window_id} => {
                 let dm = self.display.read().unwrap();
-                let pos = dm.get_window_pos(&self.id_map[&window_id]);
+                let pos = dm.get_window_pos(&window_id);
                 return Ok(Option::from(Packet::Position {x:pos.x,y:pos.y}));
             }
             Packet::RequestWindowSize { window_id } => {
                 let dm = self.display.read().unwrap();
-                let size = dm.get_window_size(&self.id_map[&window_id]);
+                let size = dm.get_window_size(&window_id);
                 return Ok(Option::from(Packet::Size {width:size.width,height:size.height}));
-            }
-            Packet::Paint { window_id, buffer} => {
-                let mut  dm = self.display.write().unwrap();
-                dm.update_window_frame_buffer(&self.id_map[&window_id], &buffer);
             }*/
+            Packet::Paint {
+                window_id,
+                width,
+                height,
+                buffer,
+            } => {
+                self.check_owner(connection_id, window_id)?;
+                Self::check_size(width, height)?;
+                // Drawn straight from the client's buffer rather than copied
+                let frame = Image::from_shm(width, height, Self::map_buffer(&buffer, width, height, "Paint")?);
+                let mut dm = self.display.write().unwrap();
+                dm.update_window_frame_buffer(&window_id, frame);
+            }
             Packet::Close { window_id } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.remove_window(&window_id);
+                return Ok(Option::from(Packet::WindowEvent {
+                    window_id,
+                    event: Event::Closed,
+                }));
+            }
+            Packet::SetCursor { window_id, shape } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.set_window_cursor(&window_id, ClientCursor::Shape(shape));
+            }
+            Packet::SetCustomCursor {
+                window_id,
+                width,
+                height,
+                hotspot_x,
+                hotspot_y,
+                buffer,
+            } => {
+                self.check_owner(connection_id, window_id)?;
+                Self::check_size_up_to(width, height, MAX_CURSOR_SIZE)?;
+                if !(0..width).contains(&hotspot_x) || !(0..height).contains(&hotspot_y) {
+                    return Err(UnixSocketError::protocol(
+                        ErrorCode::InvalidRequest,
+                        format!(
+                            "hotspot ({}, {}) is outside the {}x{} cursor",
+                            hotspot_x, hotspot_y, width, height
+                        ),
+                    ));
+                }
+                let image = Self::read_buffer(&buffer, width, height, "SetCustomCursor")?;
+                let mut dm = self.display.write().unwrap();
+                let cursor = Cursor::new(image, hotspot_x, hotspot_y);
+                dm.set_window_cursor(&window_id, ClientCursor::Image(cursor));
+            }
+            Packet::SetSizeLimits {
+                window_id,
+                min_width,
+                min_height,
+                max_width,
+                max_height,
+            } => {
+                self.check_owner(connection_id, window_id)?;
+                Self::check_size(min_width, min_height)?;
+                let max = match (max_width, max_height) {
+                    (None, None) => None,
+                    (max_width, max_height) => {
+                        let max_width = max_width.unwrap_or(MAX_SURFACE_SIZE);
+                        let max_height = max_height.unwrap_or(MAX_SURFACE_SIZE);
+                        Self::check_size(max_width, max_height)?;
+                        if max_width < min_width || max_height < min_height {
+                            return Err(UnixSocketError::protocol(
+                                ErrorCode::InvalidSize,
+                                format!(
+                                    "maximum {}x{} is below minimum {}x{}",
+                                    max_width, max_height, min_width, min_height
+                                ),
+                            ));
+                        }
+                        Some(Size {
+                            width: max_width,
+                            height: max_height,
+                        })
+                    }
+                };
+                let min = Size {
+                    width: min_width,
+                    height: min_height,
+                };
+                let mut dm = self.display.write().unwrap();
+                dm.set_window_size_limits(&window_id, min, max);
+            }
+            Packet::Maximize { window_id } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.maximize_window(&window_id);
+            }
+            Packet::Unmaximize { window_id } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.unmaximize_window(&window_id);
+            }
+            Packet::Minimize { window_id } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.minimize_window(&window_id);
+            }
+            Packet::Fullscreen { window_id } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.fullscreen_window(&window_id);
+            }
+            Packet::Unfullscreen { window_id } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.unfullscreen_window(&window_id);
+            }
+            Packet::AckConfigure { window_id, serial } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                if !dm.ack_window_configure(&window_id, serial) {
+                    return Err(UnixSocketError::protocol(
+                        ErrorCode::InvalidRequest,
+                        format!("no pending configure {} for window {}", serial, window_id),
+                    ));
+                }
+            }
+            Packet::SetTitle { window_id, title } => {
+                self.check_owner(connection_id, window_id)?;
+                Self::check_text(&title, "title")?;
+                let mut dm = self.display.write().unwrap();
+                dm.set_window_title(&window_id, title);
+            }
+            Packet::SetAppId { window_id, app_id } => {
+                self.check_owner(connection_id, window_id)?;
+                Self::check_text(&app_id, "app ID")?;
+                let mut dm = self.display.write().unwrap();
+                dm.set_window_app_id(&window_id, app_id);
+            }
+            Packet::SetIcon {
+                window_id,
+                width,
+                height,
+                buffer,
+            } => {
+                self.check_owner(connection_id, window_id)?;
+                Self::check_size_up_to(width, height, MAX_ICON_SIZE)?;
+                let icon = Self::read_buffer(&buffer, width, height, "SetIcon")?;
+                let mut dm = self.display.write().unwrap();
+                dm.set_window_icon(&window_id, Some(icon));
+            }
+            Packet::RemoveIcon { window_id } => {
+                self.check_owner(connection_id, window_id)?;
                 let mut dm = self.display.write().unwrap();
-                dm.remove_window(&self.id_map[&window_id]);
-                self.id_map.remove(&window_id);
-                return Ok(Option::from(Packet::Closed));
+                dm.set_window_icon(&window_id, None);
+            }
+            Packet::SetResizable {
+                window_id,
+                resizable,
+            } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.set_window_resizable(&window_id, resizable);
+            }
+            Packet::SetDecorations {
+                window_id,
+                decorated,
+            } => {
+                self.check_owner(connection_id, window_id)?;
+                let mut dm = self.display.write().unwrap();
+                dm.set_window_decorations(&window_id, decorated);
+            }
+            Packet::SetWallpaper { mode, fill, image } => {
+                if !negotiated.supports(Capabilities::WALLPAPER) {
+                    return Err(UnixSocketError::protocol(
+                        ErrorCode::InvalidRequest,
+                        "the wallpaper can only be set through the control socket".to_string(),
+                    ));
+                }
+                match image.as_fd() {
+                    Some(fd) => {
+                        let file = File::from(fd.try_clone_to_owned()?);
+                        wallpaper::check_image_file(&file)
+                            .map_err(|e| UnixSocketError::protocol(ErrorCode::InvalidImage, e.to_string()))?;
+                        self.wallpaper_decoder.decode(&self.display, connection_id, file, mode, fill);
+                    }
+                    None => {
+                        let mut dm = self.display.write().unwrap();
+                        self.wallpaper_decoder.supersede();
+                        dm.set_wallpaper(Wallpaper::new(fill));
+                    }
+                }
             }
             _ => {
-                println!("New packet received: {:?}", packet);
+                return Err(UnixSocketError::protocol(
+                    ErrorCode::InvalidRequest,
+                    format!("{} is not a client request", packet.name()),
+                ));
             }
         }
         Ok(None)
     }
+
+    fn connection_closed(&mut self, connection_id: u64) {
+        let mut dm = self.display.write().unwrap();
+        dm.remove_connection_windows(connection_id);
+    }
 }
 
 fn main() {
+    let headless = match HeadlessConfig::from_args(std::env::args().skip(1)) {
+        Ok(headless) => headless,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     let prism = Prism::new();
+    prism.display.write().unwrap().set_wallpaper(Wallpaper::from_env());
     let term = Arc::new(AtomicBool::new(false));
     let dm_server = Arc::clone(&prism.display);
-    let net_net_server = CompositorServer::new(Box::new(prism));
-    let mut control = net_net_server
-        .spawn()
-        .expect("net_net_server failed to start");
+    let net_net_server =
+        CompositorServer::new(Box::new(prism)).expect("failed to create compositor server");
+    dm_server
+        .write()
+        .unwrap()
+        .set_event_sender(net_net_server.event_sender());
+    let mut control = match net_net_server.spawn() {
+        Ok(control) => control,
+        Err(e) => {
+            eprintln!("Failed to start compositor server: {}", e);
+            std::process::exit(1);
+        }
+    };
     signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&term))
         .expect("failed to set up SIGTERM hook");
     signal_hook::flag::register(signal_hook::consts::SIGQUIT, Arc::clone(&term))
         .expect("failed to set up SIGTERM hook");
 
-    #[cfg(debug_assertions)]
-    {
-        debug_screen::start_screen(dm_server, term);
-    }
-    #[cfg(not(debug_assertions))]
-    {
-        release_screen::start_screen(dm_server, term);
+    if let Some(config) = headless {
+        headless_screen::start_screen(dm_server, term, config);
+    } else {
+        #[cfg(debug_assertions)]
+        {
+            debug_screen::start_screen(dm_server, term);
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            release_screen::start_screen(dm_server, term);
+        }
     }
     control.stop().expect("failed to stop net server");
     println!("Shutting down compositor server...");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create(prism: &mut Prism, connection_id: u64, negotiated: &Negotiated) -> net::Result<Option<Packet>> {
+        let packet = Packet::Create {
+            width: 64,
+            height: 48,
+            title: None,
+        };
+        prism.handle_packet(connection_id, negotiated, packet)
+    }
+
+    #[test]
+    fn second_window_of_single_window_client_is_refused() {
+        let mut prism = Prism::new();
+        let negotiated = Negotiated {
+            minor: libprotocol::PROTOCOL_VERSION.1,
+            capabilities: Capabilities::SHM_BUFFERS,
+        };
+        assert!(matches!(create(&mut prism, 1, &negotiated), Ok(Some(Packet::CreateSuccess { .. }))));
+        assert!(matches!(
+            create(&mut prism, 1, &negotiated),
+            Err(UnixSocketError::Protocol(ErrorCode::InvalidRequest, _))
+        ));
+        // Other single-window clients are unaffected
+        assert!(matches!(create(&mut prism, 2, &negotiated), Ok(Some(Packet::CreateSuccess { .. }))));
+    }
+
+    #[test]
+    fn requests_for_own_closed_window_are_not_held_against_client() {
+        let mut prism = Prism::new();
+        let negotiated = Negotiated {
+            minor: libprotocol::PROTOCOL_VERSION.1,
+            capabilities: Capabilities::SHM_BUFFERS,
+        };
+        let Ok(Some(Packet::CreateSuccess { window_id })) = create(&mut prism, 1, &negotiated) else {
+            panic!("window was not created");
+        };
+        prism.handle_packet(1, &negotiated, Packet::Close { window_id }).unwrap();
+        let set_title = || Packet::SetTitle {
+            window_id,
+            title: Some("Late".to_string()),
+        };
+        assert!(matches!(
+            prism.handle_packet(1, &negotiated, set_title()),
+            Err(UnixSocketError::ClosedWindow(id)) if id == window_id
+        ));
+        assert!(matches!(
+            prism.handle_packet(2, &negotiated, set_title()),
+            Err(UnixSocketError::Protocol(ErrorCode::UnknownWindow, _))
+        ));
+    }
+
+    #[test]
+    fn multi_window_client_can_create_several_windows() {
+        let mut prism = Prism::new();
+        let negotiated = Negotiated {
+            minor: libprotocol::PROTOCOL_VERSION.1,
+            capabilities: Capabilities::SHM_BUFFERS | Capabilities::MULTI_WINDOW,
+        };
+        for _ in 0..2 {
+            assert!(matches!(create(&mut prism, 1, &negotiated), Ok(Some(Packet::CreateSuccess { .. }))));
+        }
+    }
+}
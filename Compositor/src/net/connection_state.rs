@@ -1,47 +1,102 @@
-use std::time::Instant;
+use libprotocol::{Negotiated, PacketCodec};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+/// A client socket with its framing state.
+pub struct Connection {
+    pub stream: UnixStream,
+    pub codec: PacketCodec,
+    pub stats: ConnectionStats,
+    /// Set once the client's `Hello` has been accepted.
+    pub negotiated: Option<Negotiated>,
+    /// Whether the poller is also watching for writability.
+    pub wants_write: bool,
+    /// Whether the client came in through the control socket.
+    pub control: bool,
+}
+
+impl Connection {
+    pub fn new(stream: UnixStream, max_frame_size: usize, control: bool) -> Self {
+        Self {
+            stream,
+            codec: PacketCodec::with_max_frame_size(max_frame_size),
+            stats: ConnectionStats::new(Instant::now()),
+            negotiated: None,
+            wants_write: false,
+            control,
+        }
+    }
+}
+
 pub struct ConnectionStats {
     connected_at: Instant,
-    last_activity: Instant,
     error_count: u32,
-    recovery_attempts: u32,
+    protocol_errors: u32,
+    /// When the current count of protocol errors started.
+    protocol_errors_since: Instant,
 }
 
 impl ConnectionStats {
-    pub fn new(
-        connected_at: Instant,
-        last_activity: Instant,
-        error_count: u32,
-        recovery_attempts: u32,
-    ) -> Self {
+    pub fn new(connected_at: Instant) -> Self {
         Self {
             connected_at,
-            last_activity,
-            error_count,
-            recovery_attempts,
+            error_count: 0,
+            protocol_errors: 0,
+            protocol_errors_since: connected_at,
         }
     }
-    
-    pub fn error_rest(&mut self) {
-        self.error_count = 0;
+
+    pub fn uptime(&self) -> Duration {
+        self.connected_at.elapsed()
     }
-    
+
     pub fn add_error(&mut self) {
         self.error_count += 1;
     }
-    
-    pub fn last_activity(&self) -> Instant {
-        self.last_activity
-    }
-    
+
     pub fn errors(&self) -> u32 {
         self.error_count
     }
-    
-    pub fn recovery_attempts_increase(&mut self) {
-        self.recovery_attempts += 1;
+
+    /// Counts a protocol error at `now`, forgetting the earlier ones once
+    /// `window` has passed since the first of them.
+    pub fn add_protocol_error(&mut self, now: Instant, window: Duration) {
+        if now.duration_since(self.protocol_errors_since) >= window {
+            self.protocol_errors = 0;
+            self.protocol_errors_since = now;
+        }
+        self.protocol_errors += 1;
+    }
+
+    /// Protocol errors counted in the current window.
+    pub fn protocol_errors(&self) -> u32 {
+        self.protocol_errors
     }
-    
-    pub fn recovery_attempts(&self) -> u32 {
-        self.recovery_attempts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn protocol_errors_within_the_window_add_up() {
+        let start = Instant::now();
+        let mut stats = ConnectionStats::new(start);
+        for second in 0..3 {
+            stats.add_protocol_error(start + Duration::from_secs(second), WINDOW);
+        }
+        assert_eq!(stats.protocol_errors(), 3);
+    }
+
+    #[test]
+    fn protocol_errors_are_forgotten_after_the_window() {
+        let start = Instant::now();
+        let mut stats = ConnectionStats::new(start);
+        stats.add_protocol_error(start, WINDOW);
+        stats.add_protocol_error(start + Duration::from_secs(1), WINDOW);
+        stats.add_protocol_error(start + WINDOW, WINDOW);
+        assert_eq!(stats.protocol_errors(), 1);
     }
 }
@@ -1,4 +1,5 @@
 use std::io;
+use libprotocol::ErrorCode;
 use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum UnixSocketError {
@@ -8,18 +9,48 @@ pub enum UnixSocketError {
     #[error("Socket already exists and couldn't be removed: {0}")]
     SocketExists(String),
 
+    #[error("Another compositor already holds {0}")]
+    AlreadyRunning(String),
+
     #[error("Failed to set socket permissions: {0}")]
     PermissionError(String),
 
     #[error("Serialization error: {0}")]
     SerializationError(String),
 
-    #[error("Connection to window {0} not found")]
+    #[error("Connection {0} not found")]
     ConnectionNotFound(u64),
 
+
     #[error("Failed to send packet: {0}")]
     SendError(String),
 
     #[error("Recovery failed: {0}")]
     RecoveryFailed(String),
+
+    /// A client request was invalid; the client gets a `Packet::Error` reply.
+    #[error("Protocol error {0}: {1}")]
+    Protocol(ErrorCode, String),
+
+    /// A request named a window the client owned before it was closed; it is
+    /// answered like an unknown window but not held against the client.
+    #[error("Window {0} is already closed")]
+    ClosedWindow(u64),
+
+    #[error("Connection {0} sent too many invalid requests")]
+    Misbehaving(u64),
+
+    #[error("Connection {0} speaks an incompatible protocol version")]
+    IncompatibleVersion(u64),
+}
+
+impl UnixSocketError {
+    pub fn protocol(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::Protocol(code, message.into())
+    }
+
+    /// Whether the connection has to be dropped after this error.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Misbehaving(_) | Self::IncompatibleVersion(_))
+    }
 }
\ No newline at end of file
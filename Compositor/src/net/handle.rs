@@ -2,10 +2,13 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use std::thread::JoinHandle;
+use libprotocol::Packet;
 use crate::net::error::UnixSocketError;
+use crate::net::poller::Waker;
 
 pub struct NetHandle {
     control_sender: Sender<ControlMessage>,
+    waker: Waker,
     running: Arc<AtomicBool>,
     thread_handle: Option<JoinHandle<()>>,
 }
@@ -17,9 +20,10 @@ pub enum ControlMessage {
 }
 
 impl NetHandle {
-    pub fn new(control_sender: Sender<ControlMessage>,running: Arc<AtomicBool>,thread_handle: Option<JoinHandle<()>>) -> Self {
+    pub fn new(control_sender: Sender<ControlMessage>,waker: Waker,running: Arc<AtomicBool>,thread_handle: Option<JoinHandle<()>>) -> Self {
         Self {
             control_sender,
+            waker,
             running,
             thread_handle,
         }
@@ -29,6 +33,7 @@ impl NetHandle {
         println!("Stopping compositor server");
         self.running.store(false, Ordering::SeqCst);
         let _ = self.control_sender.send(ControlMessage::Stop);
+        self.waker.wake();
 
         if let Some(handle) = self.thread_handle.take() {
             handle.join().map_err(|_| {
@@ -44,6 +49,7 @@ impl NetHandle {
         self.control_sender
             .send(ControlMessage::Pause)
             .map_err(|_| UnixSocketError::SendError("Failed to send pause message".into()))?;
+        self.waker.wake();
         Ok(())
     }
 
@@ -52,6 +58,7 @@ impl NetHandle {
         self.control_sender
             .send(ControlMessage::Resume)
             .map_err(|_| UnixSocketError::SendError("Failed to send resume message".into()))?;
+        self.waker.wake();
         Ok(())
     }
 
@@ -64,4 +71,27 @@ impl Drop for NetHandle {
     fn drop(&mut self) {
         let _ = self.stop();
     }
-}
\ No newline at end of file
+}
+
+/// Queues packets for a client connection from outside the server thread.
+///
+/// Sending wakes the server thread, which delivers queued packets right away.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<(u64, Packet)>,
+    waker: Waker,
+}
+
+impl EventSender {
+    pub fn new(sender: Sender<(u64, Packet)>, waker: Waker) -> Self {
+        Self { sender, waker }
+    }
+
+    pub fn send(&self, connection_id: u64, packet: Packet) {
+        // The receiver only goes away when the server stops, at which point
+        // there is nobody left to deliver to
+        if self.sender.send((connection_id, packet)).is_ok() {
+            self.waker.wake();
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::net::error::UnixSocketError;
+use std::fs::{File, TryLockError};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Exclusive lock held for as long as a compositor serves a display.
+///
+/// The lock file is left in place when the lock is dropped. Removing it would
+/// let one instance lock the old file while another locks a new one.
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Takes the lock at `path`, failing if another compositor holds it.
+    pub fn acquire(path: &Path) -> crate::net::Result<Self> {
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => {
+                Err(UnixSocketError::AlreadyRunning(path.display().to_string()))
+            }
+            Err(TryLockError::Error(e)) => Err(UnixSocketError::Io(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn lock_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("prism-{}-{}.lock", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn second_acquire_fails() {
+        let path = lock_path("second-acquire");
+        let _lock = InstanceLock::acquire(&path).unwrap();
+        assert!(matches!(
+            InstanceLock::acquire(&path),
+            Err(UnixSocketError::AlreadyRunning(_))
+        ));
+    }
+
+    #[test]
+    fn dropped_lock_can_be_taken_again_and_file_stays() {
+        let path = lock_path("reacquire");
+        drop(InstanceLock::acquire(&path).unwrap());
+        assert!(path.exists());
+        assert!(InstanceLock::acquire(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -2,118 +2,176 @@ pub mod error;
 mod net_state;
 mod connection_state;
 pub mod handle;
+mod lock;
+mod poller;
 
 use crate::net::error::UnixSocketError;
-use crate::net::handle::{ControlMessage, NetHandle};
-use crate::net::net_state::ServerState;
+use crate::net::handle::{ControlMessage, EventSender, NetHandle};
+use crate::net::lock::InstanceLock;
+use crate::net::net_state::{Listeners, ServerState};
+use crate::net::poller::Waker;
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::net::UnixListener;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::{self};
-use std::time::Duration;
-use libprotocol::{Packet, WINDOW_UNIX_SOCKET_NAME};
+use libprotocol::{Negotiated, Packet, DEFAULT_MAX_FRAME_SIZE};
 
 
 pub type Result<T> = std::result::Result<T, UnixSocketError>;
 
 pub trait PacketHandler: Send {
-    fn handle_packet(&mut self, window_id: u64, packet: Packet) -> Result<Option<Packet>>;
+    /// Handles a request from a connection that completed the `Hello`
+    /// exchange with the features in `negotiated`.
+    fn handle_packet(
+        &mut self,
+        connection_id: u64,
+        negotiated: &Negotiated,
+        packet: Packet,
+    ) -> Result<Option<Packet>>;
+
+    /// Called once a connection is gone so its windows can be cleaned up.
+    fn connection_closed(&mut self, connection_id: u64);
 }
 
 pub struct CompositorServer {
-    max_recovery_attempts: u32,
     packet_handler: Arc<Mutex<Box<dyn PacketHandler + Send>>>,
+    event_tx: Sender<(u64, Packet)>,
+    event_rx: Option<Receiver<(u64, Packet)>>,
+    waker: Waker,
+    max_frame_size: usize,
 }
 
 impl CompositorServer {
-    pub fn new(packet_handler: Box<dyn PacketHandler + Send>) -> Self {
-        Self {
-            max_recovery_attempts: 3,
+    pub fn new(packet_handler: Box<dyn PacketHandler + Send>) -> Result<Self> {
+        let (event_tx, event_rx) = channel();
+        Ok(Self {
             packet_handler: Arc::new(Mutex::new(packet_handler)),
-        }
+            event_tx,
+            event_rx: Some(event_rx),
+            waker: Waker::new()?,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        })
+    }
+
+    /// Returns a handle for pushing packets to clients from other threads.
+    pub fn event_sender(&self) -> EventSender {
+        EventSender::new(self.event_tx.clone(), self.waker.clone())
     }
 
-    pub fn spawn(self) -> Result<NetHandle> {
+    /// Binds the display and control sockets and starts serving them on a
+    /// new thread.
+    ///
+    /// Fails with `AlreadyRunning` if another compositor serves the display.
+    pub fn spawn(mut self) -> Result<NetHandle> {
+        let server_state = self.initialize_server()?;
         let (control_tx, control_rx) = channel();
         let running = Arc::new(AtomicBool::new(true));
         let running_clone = running.clone();
+        let waker = self.waker.clone();
 
         let thread_handle = thread::Builder::new()
             .name("compositor-server".into())
             .spawn(move || {
-                self.run_server_loop(control_rx, running_clone);
+                Self::run_server_loop(server_state, control_rx, running_clone);
             })
             .map_err(|e| UnixSocketError::Io(e))?;
 
         Ok(NetHandle::new(
             control_tx,
+            waker,
             running,
             Some(thread_handle),
         ))
     }
 
-    fn initialize_server(&self) -> Result<ServerState> {
+    fn initialize_server(&mut self) -> Result<ServerState> {
         println!("Initializing server state");
 
-        if Path::new(&WINDOW_UNIX_SOCKET_NAME).exists() {
-            fs::remove_file(&WINDOW_UNIX_SOCKET_NAME)?;
-        }
-
-        let listener = UnixListener::bind(&WINDOW_UNIX_SOCKET_NAME)?;
-        listener.set_nonblocking(true)?;
+        let socket_path = libprotocol::socket_path()?;
+        let lock = InstanceLock::acquire(&libprotocol::lock_path(&socket_path))?;
 
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            fs::set_permissions(&WINDOW_UNIX_SOCKET_NAME, fs::Permissions::from_mode(0o666))?;
-        }
+        let listeners = Listeners {
+            display: Self::bind(&socket_path)?,
+            control: Self::bind(&libprotocol::control_socket_path(&socket_path))?,
+        };
 
-        Ok(ServerState::new(
-            listener,
+        let events = self
+            .event_rx
+            .take()
+            .expect("compositor server initialized twice");
+        ServerState::new(
+            listeners,
             self.packet_handler.clone(),
-            self.max_recovery_attempts,
-        ))
+            events,
+            self.waker.clone(),
+            self.max_frame_size,
+            socket_path,
+            lock,
+        )
     }
     
-    fn run_server_loop(self, control_rx: Receiver<ControlMessage>, running: Arc<AtomicBool>) {
-        println!("Starting compositor server thread");
+    /// Listens on `path`, which only the current user may connect to.
+    fn bind(path: &Path) -> Result<UnixListener> {
+        // Holding the lock means any socket left behind is from a dead instance
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        // Created owner-only, since `path` may be outside the runtime directory
+        // and must never be reachable by others, even briefly
+        let umask = unsafe { libc::umask(0o077) };
+        let listener = UnixListener::bind(path);
+        unsafe { libc::umask(umask) };
+        let listener = listener?;
+        listener.set_nonblocking(true)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        println!("Listening on {}", path.display());
+        Ok(listener)
+    }
 
-        let mut server_state = match self.initialize_server() {
-            Ok(state) => state,
-            Err(e) => {
-                eprintln!("Failed to initialize server: {}", e);
-                return;
-            }
-        };
+    fn run_server_loop(
+        mut server_state: ServerState,
+        control_rx: Receiver<ControlMessage>,
+        running: Arc<AtomicBool>,
+    ) {
+        println!("Starting compositor server thread");
 
         let mut paused = false;
 
         while running.load(Ordering::SeqCst) {
-            match control_rx.try_recv() {
-                Ok(ControlMessage::Stop) => break,
-                Ok(ControlMessage::Pause) => paused = true,
-                Ok(ControlMessage::Resume) => paused = false,
-                Err(_) => {}
+            // Nothing to do until resumed, so block on the control channel
+            if paused {
+                match control_rx.recv() {
+                    Ok(ControlMessage::Resume) => paused = false,
+                    Ok(ControlMessage::Pause) => {}
+                    Ok(ControlMessage::Stop) | Err(_) => break,
+                }
+                continue;
             }
 
-            if !paused {
-                if let Err(e) = server_state.accept_connections() {
-                    eprintln!("Error accepting connections: {}", e);
+            let mut stop = false;
+            for message in control_rx.try_iter() {
+                match message {
+                    ControlMessage::Stop => stop = true,
+                    ControlMessage::Pause => paused = true,
+                    ControlMessage::Resume => paused = false,
                 }
+            }
+            if stop {
+                break;
+            }
+            if paused {
+                continue;
+            }
 
-                if let Err(e) = server_state.process_packets() {
-                    eprintln!("Error processing packets: {}", e);
-                }
+            server_state.deliver_events();
 
-                if let Err(e) = server_state.check_connection_health() {
-                    eprintln!("Error checking connection health: {}", e);
-                }
+            if let Err(e) = server_state.poll() {
+                eprintln!("Error polling connections: {}", e);
             }
-
-            thread::sleep(Duration::from_millis(16));
         }
 
         println!("Compositor server thread stopping");
@@ -1,62 +1,118 @@
-use crate::net::connection_state::ConnectionStats;
+use crate::net::connection_state::Connection;
 use crate::net::error::UnixSocketError;
+use crate::net::lock::InstanceLock;
+use crate::net::poller::{Event, Poller, Waker};
 use crate::net::Packet;
 use crate::net::{PacketHandler};
-use libprotocol::Packet::{APIVersion, Close, RequestAPIVersion};
-use libprotocol::{PROTOCOL_VERSION, WINDOW_UNIX_SOCKET_NAME};
+use libprotocol::Packet::{Error as ErrorReply, Hello};
+use libprotocol::{
+    Capabilities, CodecError, ErrorCode, CONTROL_CAPABILITIES, PROTOCOL_CAPABILITIES,
+    PROTOCOL_VERSION,
+};
 use std::collections::HashMap;
 use std::io::ErrorKind;
-use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{fs, io};
 
+/// Invalid requests tolerated from one connection within
+/// `PROTOCOL_ERROR_WINDOW` before it is dropped.
+const MAX_PROTOCOL_ERRORS: u32 = 8;
+const PROTOCOL_ERROR_WINDOW: Duration = Duration::from_secs(10);
+/// Failed replies tolerated from one connection before it is dropped.
+const MAX_CONNECTION_ERRORS: u32 = 3;
+
+// Connection IDs count up from 1, so the top of the range is free for these
+const LISTENER_TOKEN: u64 = u64::MAX;
+const WAKER_TOKEN: u64 = u64::MAX - 1;
+const CONTROL_LISTENER_TOKEN: u64 = u64::MAX - 2;
+
+/// The sockets clients connect through.
+pub struct Listeners {
+    pub display: UnixListener,
+    /// Connections made here are also offered `CONTROL_CAPABILITIES`.
+    pub control: UnixListener,
+}
+
 pub struct ServerState {
-    listener: UnixListener,
-    connections: HashMap<u64, (UnixStream, ConnectionStats)>,
+    listeners: Listeners,
+    poller: Poller,
+    waker: Waker,
+    connections: HashMap<u64, Connection>,
+    next_connection_id: u64,
     packet_handler: Arc<Mutex<Box<dyn PacketHandler + Send>>>,
-    max_recovery_attempts: u32,
+    events: Receiver<(u64, Packet)>,
+    max_frame_size: usize,
+    socket_path: PathBuf,
+    // Released after `cleanup` has removed the socket
+    _lock: InstanceLock,
 }
 
 impl ServerState {
     pub fn new(
-        listener: UnixListener,
+        listeners: Listeners,
         packet_handler: Arc<Mutex<Box<dyn PacketHandler + Send>>>,
-        max_recovery_attempts: u32,
-    ) -> Self {
-        Self {
-            listener,
+        events: Receiver<(u64, Packet)>,
+        waker: Waker,
+        max_frame_size: usize,
+        socket_path: PathBuf,
+        lock: InstanceLock,
+    ) -> crate::net::Result<Self> {
+        let poller = Poller::new()?;
+        poller.add(listeners.display.as_fd(), LISTENER_TOKEN)?;
+        poller.add(listeners.control.as_fd(), CONTROL_LISTENER_TOKEN)?;
+        poller.add(waker.fd(), WAKER_TOKEN)?;
+        Ok(Self {
+            listeners,
+            poller,
+            waker,
             connections: HashMap::new(),
+            next_connection_id: 1,
             packet_handler,
-            max_recovery_attempts,
-        }
+            events,
+            max_frame_size,
+            socket_path,
+            _lock: lock,
+        })
     }
 
-    pub fn make_random_id(&self) -> u64 {
-        loop {
-            let id = std::random::random();
-            if !self.connections.contains_key(&id) {
-                return id;
+    /// Sleeps until a socket is ready or the waker fires, then services
+    /// everything that became ready.
+    pub fn poll(&mut self) -> crate::net::Result<()> {
+        for event in self.poller.wait(None)? {
+            match event.token {
+                LISTENER_TOKEN => self.accept_connections(false)?,
+                CONTROL_LISTENER_TOKEN => self.accept_connections(true)?,
+                WAKER_TOKEN => self.waker.drain(),
+                connection_id => self.service_connection(connection_id, event),
             }
         }
+        Ok(())
     }
 
-    pub fn accept_connections(&mut self) -> crate::net::Result<()> {
-        for stream in self.listener.incoming() {
+    /// Accepts everyone waiting on the display socket, or on the control
+    /// socket if `control` is set.
+    pub fn accept_connections(&mut self, control: bool) -> crate::net::Result<()> {
+        let listener = if control {
+            &self.listeners.control
+        } else {
+            &self.listeners.display
+        };
+        for stream in listener.incoming() {
             match stream {
                 Ok(stream) => {
-                    let window_id = self.make_random_id();
+                    let connection_id = self.next_connection_id;
+                    self.next_connection_id += 1;
                     stream.set_nonblocking(true)?;
-                    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
-                    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+                    self.poller.add(stream.as_fd(), connection_id)?;
 
-                    let stats = ConnectionStats::new(Instant::now(), Instant::now(), 0, 0);
+                    self.connections.insert(connection_id, Connection::new(stream, self.max_frame_size, control));
 
-                    let stream_clone = stream.try_clone()?;
-                    self.connections.insert(window_id, (stream_clone, stats));
-
-                    println!("New connection established for window {}", window_id);
+                    println!("New connection established: {}", connection_id);
                 }
                 Err(e) if e.kind() == ErrorKind::WouldBlock => {
                     break;
@@ -69,161 +125,257 @@ impl ServerState {
         Ok(())
     }
 
-    pub fn process_packets(&mut self) -> crate::net::Result<()> {
-        let mut connections = std::mem::take(&mut self.connections);
-        for (&window_id, (stream, stats)) in &mut connections {
-            if let Err(e) = self.handle_connection(window_id, stream) {
-                eprintln!("Error handling connection {}: {}", window_id, e);
-                stats.add_error();
+    /// Sends packets queued through an `EventSender` to their connections.
+    pub fn deliver_events(&mut self) {
+        while let Ok((connection_id, packet)) = self.events.try_recv() {
+            match self.connections.get_mut(&connection_id) {
+                Some(connection) => {
+                    // Clients that did not ask for a kind of event never get it
+                    let Some(packet) = connection
+                        .negotiated
+                        .and_then(|negotiated| packet.for_peer(&negotiated))
+                    else {
+                        continue;
+                    };
+                    if let Err(e) = send_packet(connection, &packet) {
+                        println!("WARN: Failed to deliver event to connection {}: {}", connection_id, e);
+                    }
+                    watch_writes(&self.poller, connection_id, connection);
+                }
+                None => println!("WARN: Dropping event for closed connection {}", connection_id),
+            }
+        }
+    }
 
-                if stats.errors() >= 3 {
-                    if !self.attempt_recovery(window_id)? {
-                        self.handle_connection_failure(window_id)?;
+    /// Flushes pending replies and handles every packet a ready connection
+    /// has buffered.
+    fn service_connection(&mut self, connection_id: u64, event: Event) {
+        let Some(mut connection) = self.connections.remove(&connection_id) else {
+            return;
+        };
+        if event.writable
+            && let Err(e) = connection.codec.flush(&connection.stream)
+        {
+            eprintln!("Error writing to connection {}: {}", connection_id, e);
+            return self.close_connection(connection_id, connection);
+        }
+        loop {
+            match receive_packet(&mut connection) {
+                Ok(packet) => match self.handle_packet(connection_id, &mut connection, packet) {
+                    Ok(()) => {}
+                    Err(e) if e.is_fatal() => {
+                        eprintln!("{}", e);
+                        return self.close_connection(connection_id, connection);
+                    }
+                    Err(e) => {
+                        eprintln!("Error handling connection {}: {}", connection_id, e);
+                        connection.stats.add_error();
+                        if connection.stats.errors() >= MAX_CONNECTION_ERRORS {
+                            return self.close_connection(connection_id, connection);
+                        }
+                    }
+                },
+                Err(CodecError::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(CodecError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    return self.close_connection(connection_id, connection);
+                }
+                // The bad frame was skipped, so tell the client and carry on
+                Err(e) if e.is_recoverable() => {
+                    let (request, code) = match e {
+                        CodecError::MissingFd(request) => (request, ErrorCode::InvalidBuffer),
+                        _ => ("Unknown", ErrorCode::InvalidRequest),
+                    };
+                    if let Err(e) = reject(connection_id, &mut connection, request, code, e.to_string()) {
+                        eprintln!("{}", e);
+                        return self.close_connection(connection_id, connection);
                     }
                 }
+                Err(e) => {
+                    eprintln!("Error reading from connection {}: {}", connection_id, e);
+                    return self.close_connection(connection_id, connection);
+                }
             }
         }
-        self.connections = connections;
-        Ok(())
+        if event.hangup && !event.readable {
+            return self.close_connection(connection_id, connection);
+        }
+        watch_writes(&self.poller, connection_id, &mut connection);
+        self.connections.insert(connection_id, connection);
     }
 
-    pub fn handle_connection(
+    fn handle_packet(
         &mut self,
-        window_id: u64,
-        stream: &mut UnixStream,
+        connection_id: u64,
+        connection: &mut Connection,
+        packet: Packet,
     ) -> crate::net::Result<()> {
-        let packet = receive_packet(stream)?;
-        if packet == RequestAPIVersion {
-            let (major, minor, patch) = PROTOCOL_VERSION;
-            let packet = APIVersion {
-                major,
-                minor,
-                patch,
-            };
-            send_packet(stream, &packet)?;
-            return Ok(());
+        let request = packet.name();
+        if let Hello {
+            major,
+            minor,
+            capabilities,
+            ..
+        } = packet
+        {
+            return greet(connection_id, connection, major, minor, capabilities);
         }
+        let Some(negotiated) = connection.negotiated else {
+            let message = "Hello must be sent first".to_string();
+            return reject(connection_id, connection, request, ErrorCode::InvalidRequest, message);
+        };
         let mut packet_out: Option<Packet> = None;
         if let Ok(mut handler) = self.packet_handler.lock() {
-            packet_out = handler.handle_packet(window_id, packet)?
+            packet_out = match handler.handle_packet(connection_id, &negotiated, packet) {
+                Ok(packet_out) => packet_out,
+                Err(UnixSocketError::Protocol(code, message)) => {
+                    reject(connection_id, connection, request, code, message)?;
+                    None
+                }
+                // Likely sent before the client heard of the close
+                Err(e @ UnixSocketError::ClosedWindow(_)) => {
+                    send_error(connection, request, ErrorCode::UnknownWindow, e.to_string())?;
+                    None
+                }
+                Err(e) => return Err(e),
+            }
         }
-        if let Some(packet_out) = packet_out {
-            send_packet(stream, &packet_out)?;
+        if let Some(packet_out) = packet_out.and_then(|packet| packet.for_peer(&negotiated)) {
+            send_packet(connection, &packet_out)?;
         }
         Ok(())
     }
 
-    pub(crate) fn check_connection_health(&mut self) -> crate::net::Result<()> {
-        let now = Instant::now();
-        let stale_connections: Vec<u64> = self
-            .connections
-            .iter()
-            .filter(|(_, (_, stats))| {
-                now.duration_since(stats.last_activity()) > Duration::from_secs(60)
-            })
-            .map(|(&id, _)| id)
-            .collect();
-
-        for window_id in stale_connections {
-            println!("WARN: Stale connection detected for window {}", window_id);
-            if !self.attempt_recovery(window_id)? {
-                self.handle_connection_failure(window_id)?;
-            }
+    fn close_connection(&mut self, connection_id: u64, connection: Connection) {
+        if let Err(e) = self.poller.delete(connection.stream.as_fd()) {
+            eprintln!("Failed to stop polling connection {}: {}", connection_id, e);
         }
-        Ok(())
-    }
-
-    fn handle_connection_failure(&mut self, window_id: u64) -> crate::net::Result<()> {
-        println!("WARN:Handling connection failure for window {}", window_id);
-        let failure_packet = Close { window_id };
-        self.broadcast_to_others(window_id, &failure_packet)?;
-        self.connections.remove(&window_id);
-        println!("Connection failure handled for window {}", window_id);
-        Ok(())
+        if let Ok(mut handler) = self.packet_handler.lock() {
+            handler.connection_closed(connection_id);
+        }
+        println!("Connection {} closed after {:?}", connection_id, connection.stats.uptime());
     }
 
-    fn attempt_recovery(&mut self, window_id: u64) -> crate::net::Result<bool> {
-        let mut connections = std::mem::take(&mut self.connections);
-        if let Some((stream, stats)) = connections.get_mut(&window_id) {
-            if stats.recovery_attempts() >= self.max_recovery_attempts {
-                eprintln!("Max recovery attempts reached for window {}", window_id);
-                return Ok(false);
-            }
-
-            println!("Attempting recovery for window {}", window_id);
-            stats.recovery_attempts_increase();
-            stream.set_nonblocking(false)?;
-            stream.set_read_timeout(Some(Duration::from_secs(30)))?;
-            stream.set_write_timeout(Some(Duration::from_secs(5)))?;
-            let ping = RequestAPIVersion;
-            if let Err(e) = send_packet(stream, &ping) {
-                println!(
-                    "WARN: Recovery attempt failed for window {}: {}",
-                    window_id, e
-                );
-                return Ok(false);
-            }
+    pub fn cleanup(&mut self) -> crate::net::Result<()> {
+        println!("Starting compositor cleanup");
 
-            println!("Recovery successful for window {}", window_id);
-            stats.error_rest();
-            self.connections = connections;
-            Ok(true)
-        } else {
-            Err(UnixSocketError::ConnectionNotFound(window_id))
+        for (connection_id, _) in self.connections.drain() {
+            println!("Closing connection {}", connection_id);
         }
-    }
 
-    fn get_stream(
-        &mut self,
-        window_id: u64,
-    ) -> crate::net::Result<&mut (UnixStream, ConnectionStats)> {
-        self.connections
-            .get_mut(&window_id)
-            .ok_or(UnixSocketError::ConnectionNotFound(window_id))
-    }
-
-    fn broadcast_to_others(&mut self, sender_id: u64, packet: &Packet) -> crate::net::Result<()> {
-        let connections = std::mem::take(&mut self.connections);
-        for (&window_id, _) in &connections {
-            if window_id != sender_id {
-                let (stream,_) = self.get_stream(window_id)?;
-                if let Err(e) = send_packet(stream, &packet) {
-                    println!("WARN: Failed to broadcast to window {}: {}", window_id, e);
-                }
+        for path in [libprotocol::control_socket_path(&self.socket_path), self.socket_path.clone()] {
+            if path.exists() {
+                fs::remove_file(&path).map_err(|e| {
+                    eprintln!("Failed to remove socket file: {}", e);
+                    UnixSocketError::Io(e)
+                })?;
             }
         }
-        self.connections = connections;
+
+        println!("Compositor cleanup completed successfully");
         Ok(())
     }
+}
 
-    pub fn cleanup(&mut self) -> crate::net::Result<()> {
-        println!("Starting compositor cleanup");
+/// Answers a client's `Hello`, or drops it if the major versions differ.
+fn greet(
+    connection_id: u64,
+    connection: &mut Connection,
+    major: u8,
+    minor: u8,
+    capabilities: Capabilities,
+) -> crate::net::Result<()> {
+    if connection.negotiated.is_some() {
+        let message = "Hello was already sent".to_string();
+        return reject(connection_id, connection, "Hello", ErrorCode::InvalidRequest, message);
+    }
+    let (our_major, our_minor, our_patch) = PROTOCOL_VERSION;
+    let offered = if connection.control {
+        PROTOCOL_CAPABILITIES
+    } else {
+        PROTOCOL_CAPABILITIES.difference(CONTROL_CAPABILITIES)
+    };
+    let Some(mut negotiated) = libprotocol::negotiate(major, minor, capabilities) else {
+        let reply = ErrorReply {
+            request: "Hello".to_string(),
+            code: ErrorCode::IncompatibleVersion,
+            message: format!("compositor speaks protocol {}.x, client {}.x", our_major, major),
+        };
+        send_packet(connection, &reply)?;
+        return Err(UnixSocketError::IncompatibleVersion(connection_id));
+    };
+    negotiated.capabilities = negotiated.capabilities.intersection(offered);
+    println!(
+        "Connection {} negotiated protocol {}.{} with capabilities {}",
+        connection_id, our_major, negotiated.minor, negotiated.capabilities
+    );
+    connection.negotiated = Some(negotiated);
+    let hello = Hello {
+        major: our_major,
+        minor: our_minor,
+        patch: our_patch,
+        capabilities: offered,
+    };
+    send_packet(connection, &hello)?;
+    Ok(())
+}
 
-        for (window_id, (_, _)) in self.connections.drain() {
-            println!("Closing connection for window {}", window_id);
-        }
+/// Replies to an invalid request with `Packet::Error`, failing with
+/// `Misbehaving` once the connection has sent too many of them.
+fn reject(
+    connection_id: u64,
+    connection: &mut Connection,
+    request: &str,
+    code: ErrorCode,
+    message: String,
+) -> crate::net::Result<()> {
+    println!(
+        "WARN: Rejected {} from connection {}: {}",
+        request, connection_id, message
+    );
+    connection.stats.add_protocol_error(Instant::now(), PROTOCOL_ERROR_WINDOW);
+    send_error(connection, request, code, message)?;
+    if connection.stats.protocol_errors() >= MAX_PROTOCOL_ERRORS {
+        return Err(UnixSocketError::Misbehaving(connection_id));
+    }
+    Ok(())
+}
 
-        if Path::new(&WINDOW_UNIX_SOCKET_NAME).exists() {
-            fs::remove_file(&WINDOW_UNIX_SOCKET_NAME).map_err(|e| {
-                eprintln!("Failed to remove socket file: {}", e);
-                UnixSocketError::Io(e)
-            })?;
-        }
+fn send_error(
+    connection: &mut Connection,
+    request: &str,
+    code: ErrorCode,
+    message: String,
+) -> crate::net::Result<()> {
+    let reply = ErrorReply {
+        request: request.to_string(),
+        code,
+        message,
+    };
+    send_packet(connection, &reply)?;
+    Ok(())
+}
 
-        println!("Compositor cleanup completed successfully");
-        Ok(())
+/// Asks the poller for writability only while replies are waiting to go out.
+fn watch_writes(poller: &Poller, connection_id: u64, connection: &mut Connection) {
+    let wants_write = connection.codec.has_pending_writes();
+    if wants_write == connection.wants_write {
+        return;
+    }
+    match poller.set_writable(connection.stream.as_fd(), connection_id, wants_write) {
+        Ok(()) => connection.wants_write = wants_write,
+        Err(e) => eprintln!("Failed to update polling for connection {}: {}", connection_id, e),
     }
 }
 
-fn receive_packet(stream: &mut UnixStream) -> io::Result<Packet> {
-    let  ret = libprotocol::receive_packet(stream);
+fn receive_packet(connection: &mut Connection) -> Result<Packet, CodecError> {
+    let ret = connection.codec.receive(&connection.stream);
     if let Ok(packet) = &ret {
         println!("LibCrystalMatrix: Received packet: {:?}", packet);
     }
     ret
 }
 
-fn send_packet(stream: &mut UnixStream, packet: &Packet) -> io::Result<()> {
+fn send_packet(connection: &mut Connection, packet: &Packet) -> io::Result<()> {
     println!("LibCrystalMatrix: Sending packet {:?}", packet);
-    libprotocol::send_packet(stream, packet)
-}
\ No newline at end of file
+    connection.codec.send(&connection.stream, packet)
+}
@@ -0,0 +1,247 @@
+//! A GL context owned by the compositor instead of a windowing library.
+//!
+//! Prefers Mesa's surfaceless platform, which needs neither a GPU nor a
+//! display server and so runs on llvmpipe. Without it the default display is
+//! used with a 1x1 pbuffer. Either way nothing is drawn to the context's own
+//! surface; the renderer composes into an offscreen `FrameBuffer`.
+
+use libloading::Library;
+use std::ffi::{CStr, c_char, c_void};
+use std::ptr::null_mut;
+use thiserror::Error;
+
+type EGLDisplay = *mut c_void;
+type EGLConfig = *mut c_void;
+type EGLContext = *mut c_void;
+type EGLSurface = *mut c_void;
+type EGLint = i32;
+type EGLBoolean = u32;
+type EGLenum = u32;
+
+const EGL_NONE: EGLint = 0x3038;
+const EGL_SUCCESS: EGLint = 0x3000;
+const EGL_EXTENSIONS: EGLint = 0x3055;
+const EGL_RED_SIZE: EGLint = 0x3024;
+const EGL_GREEN_SIZE: EGLint = 0x3023;
+const EGL_BLUE_SIZE: EGLint = 0x3022;
+const EGL_ALPHA_SIZE: EGLint = 0x3021;
+const EGL_SURFACE_TYPE: EGLint = 0x3033;
+const EGL_PBUFFER_BIT: EGLint = 0x0001;
+const EGL_RENDERABLE_TYPE: EGLint = 0x3040;
+const EGL_OPENGL_BIT: EGLint = 0x0008;
+const EGL_WIDTH: EGLint = 0x3057;
+const EGL_HEIGHT: EGLint = 0x3056;
+const EGL_OPENGL_API: EGLenum = 0x30A2;
+const EGL_CONTEXT_MAJOR_VERSION: EGLint = 0x3098;
+const EGL_CONTEXT_MINOR_VERSION: EGLint = 0x30FB;
+const EGL_CONTEXT_OPENGL_PROFILE_MASK: EGLint = 0x30FD;
+const EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT: EGLint = 0x0001;
+const EGL_PLATFORM_SURFACELESS_MESA: EGLenum = 0x31DD;
+
+const EGL_NO_DISPLAY: EGLDisplay = null_mut();
+const EGL_NO_CONTEXT: EGLContext = null_mut();
+const EGL_NO_SURFACE: EGLSurface = null_mut();
+const EGL_DEFAULT_DISPLAY: *mut c_void = null_mut();
+
+#[derive(Error, Debug)]
+pub enum EglError {
+    #[error("Failed to load libEGL: {0}")]
+    Load(#[from] libloading::Error),
+
+    #[error("{0} failed with EGL error {1:#x}")]
+    Call(&'static str, EGLint),
+
+    #[error("No EGL config supports desktop OpenGL")]
+    NoConfig,
+}
+
+/// Entry points resolved from `libEGL.so.1`.
+struct Egl {
+    get_error: unsafe extern "C" fn() -> EGLint,
+    get_proc_address: unsafe extern "C" fn(*const c_char) -> *mut c_void,
+    query_string: unsafe extern "C" fn(EGLDisplay, EGLint) -> *const c_char,
+    get_display: unsafe extern "C" fn(*mut c_void) -> EGLDisplay,
+    initialize: unsafe extern "C" fn(EGLDisplay, *mut EGLint, *mut EGLint) -> EGLBoolean,
+    terminate: unsafe extern "C" fn(EGLDisplay) -> EGLBoolean,
+    bind_api: unsafe extern "C" fn(EGLenum) -> EGLBoolean,
+    choose_config: unsafe extern "C" fn(
+        EGLDisplay,
+        *const EGLint,
+        *mut EGLConfig,
+        EGLint,
+        *mut EGLint,
+    ) -> EGLBoolean,
+    create_context:
+        unsafe extern "C" fn(EGLDisplay, EGLConfig, EGLContext, *const EGLint) -> EGLContext,
+    destroy_context: unsafe extern "C" fn(EGLDisplay, EGLContext) -> EGLBoolean,
+    create_pbuffer_surface:
+        unsafe extern "C" fn(EGLDisplay, EGLConfig, *const EGLint) -> EGLSurface,
+    destroy_surface: unsafe extern "C" fn(EGLDisplay, EGLSurface) -> EGLBoolean,
+    make_current:
+        unsafe extern "C" fn(EGLDisplay, EGLSurface, EGLSurface, EGLContext) -> EGLBoolean,
+}
+
+impl Egl {
+    fn load(library: &Library) -> Result<Self, EglError> {
+        unsafe {
+            Ok(Self {
+                get_error: *library.get(b"eglGetError")?,
+                get_proc_address: *library.get(b"eglGetProcAddress")?,
+                query_string: *library.get(b"eglQueryString")?,
+                get_display: *library.get(b"eglGetDisplay")?,
+                initialize: *library.get(b"eglInitialize")?,
+                terminate: *library.get(b"eglTerminate")?,
+                bind_api: *library.get(b"eglBindAPI")?,
+                choose_config: *library.get(b"eglChooseConfig")?,
+                create_context: *library.get(b"eglCreateContext")?,
+                destroy_context: *library.get(b"eglDestroyContext")?,
+                create_pbuffer_surface: *library.get(b"eglCreatePbufferSurface")?,
+                destroy_surface: *library.get(b"eglDestroySurface")?,
+                make_current: *library.get(b"eglMakeCurrent")?,
+            })
+        }
+    }
+
+    /// Turns a failed call into an error carrying `eglGetError`.
+    fn check(&self, call: &'static str, ok: bool) -> Result<(), EglError> {
+        if ok {
+            return Ok(());
+        }
+        match unsafe { (self.get_error)() } {
+            EGL_SUCCESS => Ok(()),
+            code => Err(EglError::Call(call, code)),
+        }
+    }
+
+    fn has_extension(&self, display: EGLDisplay, name: &str) -> bool {
+        let extensions = unsafe { (self.query_string)(display, EGL_EXTENSIONS) };
+        if extensions.is_null() {
+            return false;
+        }
+        unsafe { CStr::from_ptr(extensions) }
+            .to_string_lossy()
+            .split_whitespace()
+            .any(|extension| extension == name)
+    }
+
+    fn display(&self) -> EGLDisplay {
+        if self.has_extension(EGL_NO_DISPLAY, "EGL_MESA_platform_surfaceless") {
+            let get_platform_display =
+                unsafe { (self.get_proc_address)(c"eglGetPlatformDisplayEXT".as_ptr()) };
+            if !get_platform_display.is_null() {
+                let get_platform_display: unsafe extern "C" fn(
+                    EGLenum,
+                    *mut c_void,
+                    *const EGLint,
+                ) -> EGLDisplay = unsafe { std::mem::transmute(get_platform_display) };
+                let display = unsafe {
+                    get_platform_display(EGL_PLATFORM_SURFACELESS_MESA, EGL_DEFAULT_DISPLAY, [EGL_NONE].as_ptr())
+                };
+                if display != EGL_NO_DISPLAY {
+                    return display;
+                }
+            }
+        }
+        unsafe { (self.get_display)(EGL_DEFAULT_DISPLAY) }
+    }
+}
+
+/// A current OpenGL 4.5 core context with no window behind it.
+///
+/// The context is current on the thread that created it and is torn down on
+/// drop, so GL objects must be cleaned up before it goes.
+pub struct EglContext {
+    egl: Egl,
+    display: EGLDisplay,
+    context: EGLContext,
+    surface: EGLSurface,
+    // Dropped last; every pointer in `egl` lives in it
+    _library: Library,
+}
+
+impl EglContext {
+    pub fn new() -> Result<Self, EglError> {
+        let library = unsafe { Library::new("libEGL.so.1") }?;
+        let egl = Egl::load(&library)?;
+
+        let display = egl.display();
+        egl.check("eglGetDisplay", display != EGL_NO_DISPLAY)?;
+        let ok = unsafe { (egl.initialize)(display, null_mut(), null_mut()) } != 0;
+        egl.check("eglInitialize", ok)?;
+        let mut context = Self {
+            egl,
+            display,
+            context: EGL_NO_CONTEXT,
+            surface: EGL_NO_SURFACE,
+            _library: library,
+        };
+        context.create_context()?;
+        Ok(context)
+    }
+
+    fn create_context(&mut self) -> Result<(), EglError> {
+        let egl = &self.egl;
+        let surfaceless = egl.has_extension(self.display, "EGL_KHR_surfaceless_context");
+        let ok = unsafe { (egl.bind_api)(EGL_OPENGL_API) } != 0;
+        egl.check("eglBindAPI", ok)?;
+
+        let config_attributes = [
+            EGL_RED_SIZE, 8,
+            EGL_GREEN_SIZE, 8,
+            EGL_BLUE_SIZE, 8,
+            EGL_ALPHA_SIZE, 8,
+            EGL_RENDERABLE_TYPE, EGL_OPENGL_BIT,
+            EGL_SURFACE_TYPE, if surfaceless { 0 } else { EGL_PBUFFER_BIT },
+            EGL_NONE,
+        ];
+        let mut config: EGLConfig = null_mut();
+        let mut count = 0;
+        let ok = unsafe {
+            (egl.choose_config)(self.display, config_attributes.as_ptr(), &mut config, 1, &mut count)
+        } != 0;
+        egl.check("eglChooseConfig", ok)?;
+        if count == 0 {
+            return Err(EglError::NoConfig);
+        }
+
+        // The newest core profile llvmpipe offers, and all the shaders need
+        let context_attributes = [
+            EGL_CONTEXT_MAJOR_VERSION, 4,
+            EGL_CONTEXT_MINOR_VERSION, 5,
+            EGL_CONTEXT_OPENGL_PROFILE_MASK, EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            EGL_NONE,
+        ];
+        self.context = unsafe {
+            (egl.create_context)(self.display, config, EGL_NO_CONTEXT, context_attributes.as_ptr())
+        };
+        egl.check("eglCreateContext", self.context != EGL_NO_CONTEXT)?;
+
+        if !surfaceless {
+            let surface_attributes = [EGL_WIDTH, 1, EGL_HEIGHT, 1, EGL_NONE];
+            self.surface = unsafe {
+                (egl.create_pbuffer_surface)(self.display, config, surface_attributes.as_ptr())
+            };
+            egl.check("eglCreatePbufferSurface", self.surface != EGL_NO_SURFACE)?;
+        }
+        let ok = unsafe {
+            (egl.make_current)(self.display, self.surface, self.surface, self.context)
+        } != 0;
+        egl.check("eglMakeCurrent", ok)
+    }
+}
+
+impl Drop for EglContext {
+    fn drop(&mut self) {
+        let egl = &self.egl;
+        unsafe {
+            (egl.make_current)(self.display, EGL_NO_SURFACE, EGL_NO_SURFACE, EGL_NO_CONTEXT);
+            if self.surface != EGL_NO_SURFACE {
+                (egl.destroy_surface)(self.display, self.surface);
+            }
+            if self.context != EGL_NO_CONTEXT {
+                (egl.destroy_context)(self.display, self.context);
+            }
+            (egl.terminate)(self.display);
+        }
+    }
+}
@@ -82,6 +82,10 @@ impl FrameBuffer {
         }
     }
 
+    pub fn id(&self) -> GLuint {
+        self.framebuffer
+    }
+
     pub fn to_texture(&self) -> Texture {
         Texture::not_owned(self.texture, self.width, self.height)
     }
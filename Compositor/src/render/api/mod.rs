@@ -2,6 +2,7 @@ use std::ffi::CString;
 use std::sync::atomic::{AtomicBool, Ordering};
 use libloading::Library;
 
+pub mod egl;
 pub mod framebuffer;
 pub mod texture;
 pub mod shaderprogram;
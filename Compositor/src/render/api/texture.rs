@@ -90,19 +90,43 @@ impl Texture {
             } else {
                 self.height
             };
+            self.write_region(data, width, width, height);
+        }
+    }
+
+    /// Uploads `width * height` pixels into the top-left corner of the texture.
+    ///
+    /// `stride` is the row length of `data` in pixels, so the visible part of a
+    /// larger image can be uploaded without copying it first.
+    pub fn write_region(&mut self, data: &[u8], stride: ScreenSize, width: ScreenSize, height: ScreenSize) {
+        if !self.owns_texture || width <= 0 || height <= 0 {
+            return;
+        }
+        let width = width.min(self.width);
+        let height = height.min(self.height);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
+            gl::PixelStorei(gl::UNPACK_ROW_LENGTH, stride);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                width,
+                height,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                data.as_ptr() as *const _,
+            );
+            gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        }
+    }
+
+    /// Clears every texel to transparent black.
+    pub fn clear(&mut self) {
+        if self.owns_texture {
             unsafe {
-                gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
-                gl::TexImage2D(
-                    gl::TEXTURE_2D,
-                    0,
-                    0,
-                    0,
-                    width as i32,
-                    height as i32,
-                    gl::RGBA,
-                    gl::UNSIGNED_BYTE,
-                    data.as_ptr() as *const _,
-                );
+                gl::ClearTexImage(self.texture_id, 0, gl::RGBA, gl::UNSIGNED_BYTE, ptr::null());
             }
         }
     }
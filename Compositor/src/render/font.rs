@@ -0,0 +1,236 @@
+//! Text for compositor chrome such as window titles.
+//!
+//! A [`Font`] rasterizes glyphs on first use into one atlas image that the
+//! renderers mirror; the GL renderer re-uploads it whenever
+//! [`Font::generation`] changes. Text is laid out on a single line, one glyph
+//! per `char` with kerning and no shaping, so scripts that need joining are
+//! drawn unjoined.
+
+use crate::common;
+use crate::render::util::image::Image;
+use crate::render::util::rect::Rect;
+use libprotocol::ScreenSize;
+use libprotocol::shm::BYTES_PER_PIXEL;
+use std::collections::HashMap;
+
+/// Font used for window titles, under the shared data directory.
+pub const TITLE_FONT: &str = "fonts/DejaVuSans.ttf";
+/// Pixel size of window titles.
+pub const TITLE_FONT_SIZE: f32 = 14.0;
+/// Edge length of the glyph atlas.
+const ATLAS_SIZE: ScreenSize = 512;
+/// Empty texels around each glyph so linear filtering cannot bleed.
+const GLYPH_PADDING: ScreenSize = 1;
+const ELLIPSIS: char = '\u{2026}';
+
+/// A glyph ready to draw.
+pub struct PositionedGlyph {
+    /// Coverage of the glyph in the atlas alpha channel.
+    pub atlas: Rect,
+    /// Where the glyph goes on screen, the same size as `atlas`.
+    pub screen: Rect,
+}
+
+struct CachedGlyph {
+    atlas: Rect,
+    xmin: ScreenSize,
+    ymin: ScreenSize,
+}
+
+pub struct Font {
+    font: fontdue::Font,
+    size: f32,
+    ascent: f32,
+    line_height: ScreenSize,
+    /// White RGBA pixels with the glyph coverage in alpha.
+    atlas: Image,
+    glyphs: HashMap<char, CachedGlyph>,
+    // Shelf packer state: glyphs fill rows left to right
+    shelf_x: ScreenSize,
+    shelf_y: ScreenSize,
+    shelf_height: ScreenSize,
+    generation: u64,
+}
+
+impl Font {
+    /// Loads a TrueType or OpenType font from the shared data directory.
+    pub fn load(relative_path: &str, size: f32) -> Self {
+        let data = common::file::read_from_usr_share_to_vec(relative_path);
+        let font = fontdue::Font::from_bytes(data, fontdue::FontSettings::default())
+            .unwrap_or_else(|err| panic!("Failed to parse font {}: {}", relative_path, err));
+        let (ascent, line_height) = match font.horizontal_line_metrics(size) {
+            Some(metrics) => (metrics.ascent, metrics.new_line_size),
+            None => (size, size),
+        };
+        Self {
+            font,
+            size,
+            ascent,
+            line_height: line_height.ceil() as ScreenSize,
+            atlas: Image::new(ATLAS_SIZE, ATLAS_SIZE),
+            glyphs: HashMap::new(),
+            shelf_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+            generation: 0,
+        }
+    }
+
+    pub fn line_height(&self) -> ScreenSize {
+        self.line_height
+    }
+
+    pub fn atlas(&self) -> &Image {
+        &self.atlas
+    }
+
+    /// Changes every time the atlas contents change.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Lays out `text` on one line whose top-left corner is at `(x, y)`.
+    ///
+    /// Text wider than `max_width` is cut short and ends in an ellipsis; if
+    /// not even the ellipsis fits, nothing is drawn.
+    pub fn layout(
+        &mut self,
+        text: &str,
+        x: ScreenSize,
+        y: ScreenSize,
+        max_width: ScreenSize,
+    ) -> Vec<PositionedGlyph> {
+        let text = self.fit(text, max_width as f32);
+        // A full atlas is wiped while caching, which would invalidate glyphs
+        // cached earlier in the same pass, so cache everything once more
+        let generation = self.generation;
+        text.iter().for_each(|&ch| self.cache(ch));
+        if self.generation != generation {
+            text.iter().for_each(|&ch| self.cache(ch));
+        }
+
+        let baseline = y + self.ascent.round() as ScreenSize;
+        let mut glyphs = Vec::with_capacity(text.len());
+        self.for_each_pen_position(&text, |ch, pen| {
+            let Some(glyph) = self.glyphs.get(&ch) else {
+                return;
+            };
+            if glyph.atlas.size.width == 0 || glyph.atlas.size.height == 0 {
+                return;
+            }
+            glyphs.push(PositionedGlyph {
+                atlas: glyph.atlas,
+                screen: Rect::new(
+                    x + pen.round() as ScreenSize + glyph.xmin,
+                    baseline - glyph.ymin - glyph.atlas.size.height,
+                    glyph.atlas.size.width,
+                    glyph.atlas.size.height,
+                ),
+            });
+        });
+        glyphs
+    }
+
+    /// The characters of `text` that fit in `max_width`, ellipsized if cut.
+    fn fit(&self, text: &str, max_width: f32) -> Vec<char> {
+        let mut chars: Vec<char> = text.chars().collect();
+        if self.width(&chars) <= max_width {
+            return chars;
+        }
+        let ellipsis = self.font.metrics(ELLIPSIS, self.size).advance_width;
+        let mut fitting = 0;
+        self.for_each_pen_position(&chars, |ch, pen| {
+            let end = pen + self.font.metrics(ch, self.size).advance_width;
+            if end + ellipsis <= max_width {
+                fitting += 1;
+            }
+        });
+        chars.truncate(fitting);
+        while chars.last().is_some_and(|ch| ch.is_whitespace()) {
+            chars.pop();
+        }
+        if ellipsis <= max_width {
+            chars.push(ELLIPSIS);
+        }
+        chars
+    }
+
+    fn width(&self, chars: &[char]) -> f32 {
+        let mut width = 0.0;
+        self.for_each_pen_position(chars, |ch, pen| {
+            width = pen + self.font.metrics(ch, self.size).advance_width;
+        });
+        width
+    }
+
+    /// Calls `f` with every character and the pen position it starts at.
+    fn for_each_pen_position(&self, chars: &[char], mut f: impl FnMut(char, f32)) {
+        let mut pen = 0.0;
+        let mut previous = None;
+        for &ch in chars {
+            if let Some(previous) = previous {
+                pen += self.font.horizontal_kern(previous, ch, self.size).unwrap_or(0.0);
+            }
+            f(ch, pen);
+            pen += self.font.metrics(ch, self.size).advance_width;
+            previous = Some(ch);
+        }
+    }
+
+    /// Rasterizes `ch` into the atlas unless it is already there.
+    fn cache(&mut self, ch: char) {
+        if self.glyphs.contains_key(&ch) {
+            return;
+        }
+        let (metrics, coverage) = self.font.rasterize(ch, self.size);
+        let (width, height) = (metrics.width as ScreenSize, metrics.height as ScreenSize);
+        let Some(atlas) = self.allocate(width, height) else {
+            return;
+        };
+        let stride = ATLAS_SIZE as usize * BYTES_PER_PIXEL;
+        let pixels = self.atlas.pixels_mut();
+        for (row, line) in coverage.chunks_exact(width.max(1) as usize).enumerate() {
+            let start = (atlas.position.y as usize + row) * stride
+                + atlas.position.x as usize * BYTES_PER_PIXEL;
+            for (pixel, &alpha) in pixels[start..start + line.len() * BYTES_PER_PIXEL]
+                .chunks_exact_mut(BYTES_PER_PIXEL)
+                .zip(line)
+            {
+                pixel.copy_from_slice(&[255, 255, 255, alpha]);
+            }
+        }
+        self.glyphs.insert(
+            ch,
+            CachedGlyph {
+                atlas,
+                xmin: metrics.xmin,
+                ymin: metrics.ymin,
+            },
+        );
+        self.generation += 1;
+    }
+
+    /// Finds room for a `width` x `height` glyph, wiping the atlas when full.
+    fn allocate(&mut self, width: ScreenSize, height: ScreenSize) -> Option<Rect> {
+        let (padded_width, padded_height) = (width + GLYPH_PADDING, height + GLYPH_PADDING);
+        if padded_width > ATLAS_SIZE || padded_height > ATLAS_SIZE {
+            return None;
+        }
+        if self.shelf_x + padded_width > ATLAS_SIZE {
+            self.shelf_x = 0;
+            self.shelf_y += self.shelf_height;
+            self.shelf_height = 0;
+        }
+        if self.shelf_y + padded_height > ATLAS_SIZE {
+            self.glyphs.clear();
+            self.atlas.pixels_mut().fill(0);
+            self.shelf_x = 0;
+            self.shelf_y = 0;
+            self.shelf_height = 0;
+        }
+        let rect = Rect::new(self.shelf_x, self.shelf_y, width, height);
+        self.shelf_x += padded_width;
+        self.shelf_height = self.shelf_height.max(padded_height);
+        Some(rect)
+    }
+}
@@ -1,27 +1,67 @@
+use crate::common::cursor::Cursor;
 use crate::render::api::framebuffer::FrameBuffer;
 use crate::render::api::init_gl;
 use crate::render::api::shaderprogram::ShaderProgram;
 use crate::render::api::texture::Texture;
+use crate::render::font::{Font, PositionedGlyph, TITLE_FONT, TITLE_FONT_SIZE};
 use crate::render::util::colour::Colour;
+use crate::render::util::image::Image;
 use crate::render::util::rect::Rect;
-use crate::window::window::{WINDOW_PADDING, Window};
+use crate::window::decoration::{self, TitleButton};
+use crate::window::stack::WindowStack;
+use crate::window::window::Window;
 use crate::{common, math};
 use gl::{BLEND, ONE_MINUS_SRC_ALPHA, SRC_ALPHA};
-use nalgebra::{Matrix4, Vector2, Vector3};
-use std::collections::HashMap;
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
 use libprotocol::ScreenSize;
+use libprotocol::shm::BYTES_PER_PIXEL;
 
 pub mod api;
+pub mod font;
+pub mod software;
 pub mod util;
+pub mod wallpaper;
+
+/// The drawing operations `DisplayServer` needs from a renderer.
+///
+/// Output is built from three full-screen layers: background, windows and
+/// the mouse cursor. Each `rerender_*` call redraws one layer and `render`
+/// composes them into the final frame.
+pub trait RenderBackend: Send + Sync {
+    /// Draws a screen-sized, top-row-first `background` image.
+    fn rerender_background(&mut self, background: &Image);
+    /// Draws `cursor` with its hotspot at the pointer position `(x, y)`.
+    fn rerender_mouse(&mut self, x: ScreenSize, y: ScreenSize, cursor: &Cursor);
+    fn rerender_windows(&mut self, windows: &mut WindowStack);
+    fn render(&mut self);
+    /// Copies the last composed frame, top row first.
+    fn read_frame(&mut self) -> Image;
+    fn cleanup(&mut self);
+}
+
 #[repr(C, align(16))]
 pub struct Renderer {
     mouse_layer: FrameBuffer,
     window_layer: FrameBuffer,
     background_layer: FrameBuffer,
+    /// Where frames are composed when there is no window to draw into.
+    output: Option<FrameBuffer>,
+    /// Icons of `TitleButton::ALL`, in that order.
+    title_buttons: [Texture; 3],
+    title_font: Font,
+    /// GPU copy of the cursor last drawn and the `Cursor::id` it came from.
+    cursor: Option<Texture>,
+    cursor_id: Option<u64>,
+    /// Composed wallpaper, re-uploaded on every background rerender.
+    background: Option<Texture>,
+    /// GPU copy of the title font atlas and the generation it was copied at.
+    font_atlas: Texture,
+    font_atlas_generation: Option<u64>,
     vao: u32,
     textured_sp: ShaderProgram,
     textured_flip_sp: ShaderProgram,
     colour_sp: ShaderProgram,
+    text_sp: ShaderProgram,
     width: ScreenSize,
     height: ScreenSize,
     in_buffer: bool,
@@ -31,7 +71,18 @@ pub struct Renderer {
 }
 
 impl Renderer {
+    /// Composes into the default framebuffer of the current context.
     pub fn new(width: ScreenSize, height: ScreenSize) -> Self {
+        Self::with_output(width, height, false)
+    }
+
+    /// Composes into a framebuffer object instead, for contexts that have
+    /// no window surface.
+    pub fn new_offscreen(width: ScreenSize, height: ScreenSize) -> Self {
+        Self::with_output(width, height, true)
+    }
+
+    fn with_output(width: ScreenSize, height: ScreenSize, offscreen: bool) -> Self {
         init_gl();
         let mut vao = 0;
         unsafe {
@@ -44,10 +95,25 @@ impl Renderer {
         let texture_f_shader = common::file::read_from_usr_share("shaders/textured.frag");
         let texture_flip_f_shader = common::file::read_from_usr_share("shaders/textured_flip.frag");
         let colour_f_shader = common::file::read_from_usr_share("shaders/colour.frag");
+        let text_f_shader = common::file::read_from_usr_share("shaders/text.frag");
+        let title_font = Font::load(TITLE_FONT, TITLE_FONT_SIZE);
         Self {
             mouse_layer: FrameBuffer::new(width, height),
             window_layer: FrameBuffer::new(width, height),
             background_layer: FrameBuffer::new(width, height),
+            output: offscreen.then(|| FrameBuffer::new(width, height)),
+            title_buttons: TitleButton::ALL.map(|button| {
+                let icon = common::file::read_image_from_usr_share(button.icon_path());
+                let mut texture = Texture::new(icon.width(), icon.height());
+                texture.write(icon.pixels(), icon.width(), icon.height());
+                texture
+            }),
+            font_atlas: Texture::new(title_font.atlas().width(), title_font.atlas().height()),
+            font_atlas_generation: None,
+            cursor: None,
+            cursor_id: None,
+            background: None,
+            title_font,
             vao,
             textured_sp: ShaderProgram::new(vertex_shader.as_str(), texture_f_shader.as_str()),
             textured_flip_sp: ShaderProgram::new(
@@ -55,6 +121,7 @@ impl Renderer {
                 texture_flip_f_shader.as_str(),
             ),
             colour_sp: ShaderProgram::new(vertex_shader.as_str(), colour_f_shader.as_str()),
+            text_sp: ShaderProgram::new(vertex_shader.as_str(), text_f_shader.as_str()),
             width,
             height,
             in_buffer: false,
@@ -64,67 +131,254 @@ impl Renderer {
         }
     }
 
+}
+
+impl RenderBackend for Renderer {
     #[inline(always)]
-    pub fn rerender_background(&mut self, texture: &Texture) {
+    fn rerender_background(&mut self, background: &Image) {
+        let texture = self
+            .background
+            .get_or_insert_with(|| Texture::new(background.width(), background.height()));
+        texture.write(background.pixels(), background.width(), background.height());
+        let handle = Texture::not_owned_from(texture);
         self.in_buffer = true;
         self.background_layer.begin();
-        self.render_rect_textured(&self.screen_rect, texture);
-        self.mouse_layer.end();
+        self.render_rect_textured(&self.screen_rect, &handle);
+        self.background_layer.end();
         self.in_buffer = false;
     }
 
     #[inline(always)]
-    pub fn rerender_mouse(&mut self, x: ScreenSize, y: ScreenSize) {
+    fn rerender_mouse(&mut self, x: ScreenSize, y: ScreenSize, cursor: &Cursor) {
+        let image = cursor.image();
+        if self.cursor_id != Some(cursor.id()) {
+            let mut texture = match self.cursor.take() {
+                Some(texture)
+                    if texture.width() == image.width() && texture.height() == image.height() =>
+                {
+                    texture
+                }
+                old => {
+                    if let Some(mut old) = old {
+                        old.cleanup();
+                    }
+                    Texture::new(image.width(), image.height())
+                }
+            };
+            texture.write(image.pixels(), image.width(), image.height());
+            self.cursor = Some(texture);
+            self.cursor_id = Some(cursor.id());
+        }
+        let handle = Texture::not_owned_from(self.cursor.as_ref().expect("cursor was uploaded"));
+        let (hotspot_x, hotspot_y) = cursor.hotspot();
+        let rect = Rect::new(x - hotspot_x, y - hotspot_y, image.width(), image.height());
         self.in_buffer = true;
         self.mouse_layer.begin();
-        self.render_rect(&Rect::new(x - 1, y - 2, 15, 15), Colour::green());
+        self.render_rect_textured(&rect, &handle);
         self.mouse_layer.end();
         self.in_buffer = false;
     }
 
     #[inline(always)]
-    pub fn rerender_windows(&mut self, windows: &HashMap<u64, Window>) {
+    fn rerender_windows(&mut self, windows: &mut WindowStack) {
+        for window in windows.values_mut() {
+            Self::sync_window_content(window);
+            Self::sync_window_icon(window);
+        }
         self.in_buffer = true;
         self.window_layer.begin();
-        for window in windows.values() {
+        for (_, window) in windows.iter() {
             if !window.is_minimized() {
                 let rect = window.get_render_rect();
-                if window.has_title_bar() {
+                if window.has_decorations() {
                     self.render_rect(
-                        &Rect::new(
-                            rect.position.x,
-                            rect.position.y,
-                            rect.size.width + WINDOW_PADDING * 2,
-                            rect.size.height + 30 + WINDOW_PADDING,
-                        ),
+                        &window.get_frame_rect(),
                         Colour::grayscale_alpha(32, 225),
                     );
-                    let title_bar_y = rect.position.y;
-                    let title_bar_x = rect.position.x;
-                    if window.has_icon() {
-                        self.render_rect(
-                            &Rect::new(title_bar_x + 5, title_bar_y + 5, 20, 20),
-                            Colour::white(),
-                        );
+                    if let Some(icon) = window.get_icon_texture() {
+                        self.render_rect_textured(&decoration::icon_rect(rect), icon);
+                    }
+                    for (button, icon) in TitleButton::ALL.iter().zip(&self.title_buttons) {
+                        let button_rect = button.rect(rect);
+                        if let Some(state) = window.get_title_button_state(*button) {
+                            self.render_rect(&button_rect, button.highlight(state));
+                        }
+                        self.render_rect_textured(&button_rect, icon);
                     }
+                    if let Some(title) = window.get_title() {
+                        let glyphs = self.layout_title(window, title);
+                        for glyph in &glyphs {
+                            self.render_glyph(glyph, Colour::grayscale(230));
+                        }
+                    }
+                }
+                let body = window.get_content_rect();
+                match window.get_content() {
+                    Some(content) => self.render_rect_textured(&body, content),
+                    None => self.render_rect(&body, Colour::grayscale(64)),
                 }
-                self.render_rect(
-                    &Rect::new(
-                        rect.position.x + WINDOW_PADDING,
-                        rect.position.y + 30,
-                        rect.size.width,
-                        rect.size.height,
-                    ),
-                    Colour::grayscale(64),
-                );
-            } else if window.is_maximized() {
-                self.render_rect(&self.screen_rect, Colour::grayscale(64));
             }
         }
         self.window_layer.end();
         self.in_buffer = false;
     }
 
+    #[inline(always)]
+    fn render(&mut self) {
+        let target = self.output.as_ref().map_or(0, FrameBuffer::id);
+        unsafe {
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Enable(BLEND);
+            gl::BlendFunc(SRC_ALPHA, ONE_MINUS_SRC_ALPHA);
+        }
+        let rect = Rect::new(0, 0, self.width as ScreenSize, self.height as ScreenSize);
+        self.render_rect_textured(&rect, &self.background_layer.to_texture());
+        self.render_rect_textured(&rect, &self.window_layer.to_texture());
+        self.render_rect_textured(&rect, &self.mouse_layer.to_texture());
+        unsafe {
+            gl::Disable(BLEND);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+        }
+    }
+
+    fn read_frame(&mut self) -> Image {
+        let mut frame = Image::new(self.width, self.height);
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.output.as_ref().map_or(0, FrameBuffer::id));
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                self.width,
+                self.height,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                frame.pixels_mut().as_mut_ptr().cast(),
+            );
+        }
+        // GL reads bottom row first
+        let stride = self.width as usize * BYTES_PER_PIXEL;
+        let pixels = frame.pixels_mut();
+        for row in 0..self.height as usize / 2 {
+            let (top, bottom) = pixels.split_at_mut((self.height as usize - 1 - row) * stride);
+            top[row * stride..(row + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+        }
+        frame
+    }
+
+    fn cleanup(&mut self) {
+        self.colour_sp.cleanup();
+        self.textured_sp.cleanup();
+        self.textured_flip_sp.cleanup();
+        self.text_sp.cleanup();
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+        self.mouse_layer.cleanup();
+        self.window_layer.cleanup();
+        self.background_layer.cleanup();
+        if let Some(output) = &self.output {
+            output.cleanup();
+        }
+        for icon in &mut self.title_buttons {
+            icon.cleanup();
+        }
+        self.font_atlas.cleanup();
+        if let Some(cursor) = &mut self.cursor {
+            cursor.cleanup();
+        }
+        if let Some(background) = &mut self.background {
+            background.cleanup();
+        }
+    }
+}
+
+impl Renderer {
+    /// Keeps a window's content texture the size of the window and uploads
+    /// the client's latest frame into it, cropping whatever does not fit.
+    fn sync_window_content(window: &mut Window) {
+        if window.get_frame().is_none() {
+            return;
+        }
+        let size = window.get_size();
+        let resized = window
+            .get_content()
+            .is_none_or(|content| content.width() != size.width || content.height() != size.height);
+        if resized
+            && let Some(mut old) = window.set_content(Texture::new(size.width, size.height))
+        {
+            old.cleanup();
+        }
+        if !resized && !window.is_frame_dirty() {
+            return;
+        }
+        if let (Some(frame), Some(content)) = window.get_frame_and_content() {
+            content.clear();
+            content.write_region(frame.pixels(), frame.width(), frame.width(), frame.height());
+        }
+        window.clear_frame_dirty();
+    }
+
+    /// Uploads a window's icon the first time it is drawn after being set.
+    fn sync_window_icon(window: &mut Window) {
+        if window.get_icon_texture().is_some() {
+            return;
+        }
+        if let Some(icon) = window.get_icon() {
+            let mut texture = Texture::new(icon.width(), icon.height());
+            texture.write(icon.pixels(), icon.width(), icon.height());
+            window.set_icon_texture(texture);
+        }
+    }
+
+    /// Lays out a window title and brings the atlas texture up to date.
+    fn layout_title(&mut self, window: &Window, title: &str) -> Vec<PositionedGlyph> {
+        let area = decoration::title_rect(window);
+        let glyphs = self.title_font.layout(
+            title,
+            area.position.x,
+            area.position.y + (area.size.height - self.title_font.line_height()) / 2,
+            area.size.width,
+        );
+        let generation = self.title_font.generation();
+        if self.font_atlas_generation != Some(generation) {
+            let atlas = self.title_font.atlas();
+            self.font_atlas.write(atlas.pixels(), atlas.width(), atlas.height());
+            self.font_atlas_generation = Some(generation);
+        }
+        glyphs
+    }
+
+    /// Draws a glyph from the font atlas in a solid colour.
+    fn render_glyph(&self, glyph: &PositionedGlyph, colour: Colour) {
+        let atlas_width = self.font_atlas.width() as f32;
+        let atlas_height = self.font_atlas.height() as f32;
+        let uv_rect = Vector4::new(
+            glyph.atlas.position.x as f32 / atlas_width,
+            glyph.atlas.position.y as f32 / atlas_height,
+            glyph.atlas.size.width as f32 / atlas_width,
+            glyph.atlas.size.height as f32 / atlas_height,
+        );
+        let model = math::create_model_matrix(
+            Vector3::new(glyph.screen.position.x as f32, glyph.screen.position.y as f32, 0.0),
+            Vector2::new(glyph.screen.size.width as f32, glyph.screen.size.height as f32),
+        );
+        self.text_sp.activate();
+        let colour = colour.to_gl();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.font_atlas.id());
+            gl::UniformMatrix4fv(0, 1, gl::FALSE, model.as_ptr().cast());
+            gl::UniformMatrix4fv(1, 1, gl::FALSE, self.view.as_ptr().cast());
+            gl::UniformMatrix4fv(2, 1, gl::FALSE, self.project.as_ptr().cast());
+            gl::Uniform1i(3, 0);
+            gl::Uniform4f(4, colour.x, colour.y, colour.z, colour.w);
+            gl::Uniform4fv(5, 1, uv_rect.as_ptr().cast());
+            gl::DrawArrays(gl::TRIANGLES, 0, 6);
+        }
+    }
+
     #[inline(always)]
     fn render_rect_textured(&self, rect: &Rect, texture: &Texture) {
         self.internal_texture(
@@ -183,35 +437,6 @@ impl Renderer {
             gl::DrawArrays(gl::TRIANGLES, 0, 6);
         }
     }
-
-    #[inline(always)]
-    pub fn render(&mut self) {
-        unsafe {
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
-            gl::Enable(BLEND);
-            gl::BlendFunc(SRC_ALPHA, ONE_MINUS_SRC_ALPHA);
-        }
-        let rect = Rect::new(0, 0, self.width as ScreenSize, self.height as ScreenSize);
-        self.render_rect_textured(&rect, &self.background_layer.to_texture());
-        self.render_rect_textured(&rect, &self.window_layer.to_texture());
-        self.render_rect_textured(&rect, &self.mouse_layer.to_texture());
-        unsafe {
-            gl::Disable(BLEND);
-        }
-    }
-
-    pub fn cleanup(&mut self) {
-        self.colour_sp.cleanup();
-        self.textured_sp.cleanup();
-        self.textured_flip_sp.cleanup();
-        unsafe {
-            gl::DeleteVertexArrays(1, &self.vao);
-        }
-        self.mouse_layer.cleanup();
-        self.window_layer.cleanup();
-        self.background_layer.cleanup();
-    }
 }
 
 impl Drop for Renderer {
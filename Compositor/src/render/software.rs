@@ -0,0 +1,248 @@
+//! Pure-CPU render backend.
+//!
+//! Mirrors what the GL renderer draws, layer for layer, into RGBA images so
+//! the compositor can run on machines without a GPU. Blending follows the
+//! GL pipeline: every draw is `SRC_ALPHA, ONE_MINUS_SRC_ALPHA` applied to all
+//! four channels, and solid rectangles get the rounded-corner coverage that
+//! `colour.frag` computes.
+
+use crate::common;
+use crate::common::cursor::Cursor;
+use crate::render::RenderBackend;
+use crate::render::font::{Font, PositionedGlyph, TITLE_FONT, TITLE_FONT_SIZE};
+use crate::render::util::colour::Colour;
+use crate::render::util::image::Image;
+use crate::render::util::rect::Rect;
+use crate::window::decoration::{self, TitleButton};
+use crate::window::stack::WindowStack;
+use libprotocol::ScreenSize;
+use libprotocol::shm::BYTES_PER_PIXEL;
+
+/// Corner radius passed to `roundedBoxSDF` in `colour.frag`.
+const CORNER_RADIUS: f32 = 24.0;
+/// Width of the anti-aliased edge in `colour.frag`.
+const EDGE_SMOOTHNESS: f32 = 1.0;
+
+pub struct SoftwareRenderer {
+    mouse_layer: Image,
+    window_layer: Image,
+    background_layer: Image,
+    frame: Image,
+    /// Icons of `TitleButton::ALL`, in that order.
+    title_buttons: [Image; 3],
+    title_font: Font,
+    width: ScreenSize,
+    height: ScreenSize,
+    screen_rect: Rect,
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: ScreenSize, height: ScreenSize) -> Self {
+        Self {
+            mouse_layer: Image::new(width, height),
+            window_layer: Image::new(width, height),
+            background_layer: Image::new(width, height),
+            frame: Image::new(width, height),
+            title_buttons: TitleButton::ALL
+                .map(|button| common::file::read_image_from_usr_share(button.icon_path())),
+            title_font: Font::load(TITLE_FONT, TITLE_FONT_SIZE),
+            width,
+            height,
+            screen_rect: Rect::new(0, 0, width, height),
+        }
+    }
+
+    fn resolution(&self) -> (f32, f32) {
+        (self.width as f32, self.height as f32)
+    }
+}
+
+impl RenderBackend for SoftwareRenderer {
+    fn rerender_background(&mut self, background: &Image) {
+        clear(&mut self.background_layer);
+        draw_image(&mut self.background_layer, &self.screen_rect, background);
+    }
+
+    fn rerender_mouse(&mut self, x: ScreenSize, y: ScreenSize, cursor: &Cursor) {
+        let image = cursor.image();
+        let (hotspot_x, hotspot_y) = cursor.hotspot();
+        clear(&mut self.mouse_layer);
+        draw_image(
+            &mut self.mouse_layer,
+            &Rect::new(x - hotspot_x, y - hotspot_y, image.width(), image.height()),
+            image,
+        );
+    }
+
+    fn rerender_windows(&mut self, windows: &mut WindowStack) {
+        let resolution = self.resolution();
+        let layer = &mut self.window_layer;
+        clear(layer);
+        for (_, window) in windows.iter() {
+            if !window.is_minimized() {
+                let rect = window.get_render_rect();
+                if window.has_decorations() {
+                    fill_rect(
+                        layer,
+                        &window.get_frame_rect(),
+                        Colour::grayscale_alpha(32, 225),
+                        resolution,
+                    );
+                    if let Some(icon) = window.get_icon() {
+                        draw_image(layer, &decoration::icon_rect(rect), icon);
+                    }
+                    for (button, icon) in TitleButton::ALL.iter().zip(&self.title_buttons) {
+                        let button_rect = button.rect(rect);
+                        if let Some(state) = window.get_title_button_state(*button) {
+                            fill_rect(layer, &button_rect, button.highlight(state), resolution);
+                        }
+                        draw_image(layer, &button_rect, icon);
+                    }
+                    if let Some(title) = window.get_title() {
+                        let area = decoration::title_rect(window);
+                        let font = &mut self.title_font;
+                        let glyphs = font.layout(
+                            title,
+                            area.position.x,
+                            area.position.y + (area.size.height - font.line_height()) / 2,
+                            area.size.width,
+                        );
+                        for glyph in &glyphs {
+                            draw_glyph(layer, glyph, font.atlas(), Colour::grayscale(230));
+                        }
+                    }
+                }
+                let body = window.get_content_rect();
+                match window.get_frame() {
+                    Some(frame) => draw_image(layer, &body, frame),
+                    None => fill_rect(layer, &body, Colour::grayscale(64), resolution),
+                }
+            }
+        }
+        for window in windows.values_mut() {
+            window.clear_frame_dirty();
+        }
+    }
+
+    fn render(&mut self) {
+        clear(&mut self.frame);
+        composite(&mut self.frame, &self.background_layer);
+        composite(&mut self.frame, &self.window_layer);
+        composite(&mut self.frame, &self.mouse_layer);
+    }
+
+    fn read_frame(&mut self) -> Image {
+        Image::from_rgba(self.width, self.height, self.frame.pixels().to_vec())
+    }
+
+    fn cleanup(&mut self) {}
+}
+
+/// Fills `rect` with `colour`, fading out the rounded corners.
+///
+/// Like `colour.frag`, the distance field is evaluated with the quad
+/// stretched to the screen `resolution`, so corners shrink with the rect.
+fn fill_rect(layer: &mut Image, rect: &Rect, colour: Colour, resolution: (f32, f32)) {
+    let colour = colour.to_gl();
+    let (res_x, res_y) = resolution;
+    for_each_pixel(layer, rect, |x, y, pixel| {
+        let u = (x as f32 + 0.5 - rect.position.x as f32) / rect.size.width as f32;
+        let v = (y as f32 + 0.5 - rect.position.y as f32) / rect.size.height as f32;
+        let distance = rounded_box_sdf(
+            (u * res_x - res_x * 0.5, v * res_y - res_y * 0.5),
+            (res_x * 0.5, res_y * 0.5),
+            CORNER_RADIUS,
+        );
+        let coverage = 1.0 - smoothstep(-EDGE_SMOOTHNESS, 0.0, distance);
+        blend(pixel, [colour.x, colour.y, colour.z, colour.w * coverage]);
+    });
+}
+
+/// Draws `image` 1:1 into the top-left of `rect`; texels outside the image
+/// are transparent, like the cleared remainder of a content texture.
+fn draw_image(layer: &mut Image, rect: &Rect, image: &Image) {
+    let stride = image.width() as usize * BYTES_PER_PIXEL;
+    for_each_pixel(layer, rect, |x, y, pixel| {
+        let (u, v) = (x - rect.position.x, y - rect.position.y);
+        if u >= image.width() || v >= image.height() {
+            return;
+        }
+        let offset = v as usize * stride + u as usize * BYTES_PER_PIXEL;
+        let texel = &image.pixels()[offset..offset + BYTES_PER_PIXEL];
+        blend(pixel, [texel[0], texel[1], texel[2], texel[3]].map(|c| c as f32 / 255.0));
+    });
+}
+
+/// Draws a glyph from the font `atlas` in a solid colour.
+fn draw_glyph(layer: &mut Image, glyph: &PositionedGlyph, atlas: &Image, colour: Colour) {
+    let colour = colour.to_gl();
+    let stride = atlas.width() as usize * BYTES_PER_PIXEL;
+    for_each_pixel(layer, &glyph.screen, |x, y, pixel| {
+        let u = glyph.atlas.position.x + x - glyph.screen.position.x;
+        let v = glyph.atlas.position.y + y - glyph.screen.position.y;
+        let coverage = atlas.pixels()[v as usize * stride + u as usize * BYTES_PER_PIXEL + 3];
+        blend(pixel, [colour.x, colour.y, colour.z, colour.w * coverage as f32 / 255.0]);
+    });
+}
+
+/// Blends a full-screen layer over `frame`.
+fn composite(frame: &mut Image, layer: &Image) {
+    for (dst, src) in frame
+        .pixels_mut()
+        .chunks_exact_mut(BYTES_PER_PIXEL)
+        .zip(layer.pixels().chunks_exact(BYTES_PER_PIXEL))
+    {
+        if src[3] != 0 {
+            blend(dst, [src[0], src[1], src[2], src[3]].map(|c| c as f32 / 255.0));
+        }
+    }
+}
+
+fn clear(layer: &mut Image) {
+    layer.pixels_mut().fill(0);
+}
+
+/// Calls `f` for every pixel of `layer` whose centre lies inside `rect`.
+fn for_each_pixel(
+    layer: &mut Image,
+    rect: &Rect,
+    mut f: impl FnMut(ScreenSize, ScreenSize, &mut [u8]),
+) {
+    let x0 = rect.position.x.max(0);
+    let y0 = rect.position.y.max(0);
+    let x1 = (rect.position.x + rect.size.width).min(layer.width());
+    let y1 = (rect.position.y + rect.size.height).min(layer.height());
+    if x0 >= x1 || y0 >= y1 {
+        return;
+    }
+    let stride = layer.width() as usize * BYTES_PER_PIXEL;
+    let pixels = layer.pixels_mut();
+    for y in y0..y1 {
+        let row = &mut pixels[y as usize * stride..(y as usize + 1) * stride];
+        for x in x0..x1 {
+            let offset = x as usize * BYTES_PER_PIXEL;
+            f(x, y, &mut row[offset..offset + BYTES_PER_PIXEL]);
+        }
+    }
+}
+
+/// `glBlendFunc(SRC_ALPHA, ONE_MINUS_SRC_ALPHA)` on an 8-bit RGBA target.
+fn blend(dst: &mut [u8], src: [f32; 4]) {
+    let alpha = src[3];
+    for (channel, value) in dst.iter_mut().zip(src) {
+        let out = value * alpha + (*channel as f32 / 255.0) * (1.0 - alpha);
+        *channel = (out.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+}
+
+/// `roundedBoxSDF` from `colour.frag`.
+fn rounded_box_sdf(center: (f32, f32), size: (f32, f32), radius: f32) -> f32 {
+    let qx = center.0.abs() - size.0 + radius;
+    let qy = center.1.abs() - size.1 + radius;
+    qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - radius
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
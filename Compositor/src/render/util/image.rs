@@ -0,0 +1,127 @@
+use libprotocol::ScreenSize;
+use libprotocol::shm::{ShmMapping, BYTES_PER_PIXEL};
+
+/// CPU-side pixel data in tightly packed 8-bit RGBA.
+pub struct Image {
+    width: ScreenSize,
+    height: ScreenSize,
+    pixels: Pixels,
+}
+
+enum Pixels {
+    Owned(Vec<u8>),
+    /// A client's buffer, read in place instead of copied
+    Shared(ShmMapping),
+}
+
+impl Image {
+    /// Creates a fully transparent image.
+    pub fn new(width: ScreenSize, height: ScreenSize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: Pixels::Owned(vec![0; Self::byte_len(width, height)]),
+        }
+    }
+
+    /// Wraps existing RGBA pixels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` does not hold exactly `width * height` pixels.
+    pub fn from_rgba(width: ScreenSize, height: ScreenSize, pixels: Vec<u8>) -> Self {
+        assert_eq!(pixels.len(), Self::byte_len(width, height), "pixel data does not match image size");
+        Self {
+            width,
+            height,
+            pixels: Pixels::Owned(pixels),
+        }
+    }
+
+    /// Wraps a client's shared buffer without copying it. The pixels follow
+    /// whatever the client writes into the buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mapping` does not hold exactly `width * height` pixels.
+    pub fn from_shm(width: ScreenSize, height: ScreenSize, mapping: ShmMapping) -> Self {
+        assert_eq!(mapping.as_slice().len(), Self::byte_len(width, height), "pixel data does not match image size");
+        Self {
+            width,
+            height,
+            pixels: Pixels::Shared(mapping),
+        }
+    }
+
+    #[inline]
+    pub fn byte_len(width: ScreenSize, height: ScreenSize) -> usize {
+        width.max(0) as usize * height.max(0) as usize * BYTES_PER_PIXEL
+    }
+
+    #[inline]
+    pub fn width(&self) -> ScreenSize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> ScreenSize {
+        self.height
+    }
+
+    #[inline]
+    pub fn pixels(&self) -> &[u8] {
+        match &self.pixels {
+            Pixels::Owned(pixels) => pixels,
+            Pixels::Shared(mapping) => mapping.as_slice(),
+        }
+    }
+
+    /// Pixels to modify in place, copied out of a shared buffer first.
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        if let Pixels::Shared(mapping) = &self.pixels {
+            self.pixels = Pixels::Owned(mapping.as_slice().to_vec());
+        }
+        match &mut self.pixels {
+            Pixels::Owned(pixels) => pixels,
+            Pixels::Shared(_) => unreachable!("shared pixels were just copied"),
+        }
+    }
+
+    /// Returns a copy resized to `width` x `height` with bilinear filtering.
+    pub fn scaled(&self, width: ScreenSize, height: ScreenSize) -> Image {
+        let mut scaled = Image::new(width, height);
+        let scale_x = self.width as f32 / width as f32;
+        let scale_y = self.height as f32 / height as f32;
+        for (i, pixel) in scaled.pixels_mut().chunks_exact_mut(BYTES_PER_PIXEL).enumerate() {
+            let (x, y) = (i as ScreenSize % width, i as ScreenSize / width);
+            let texel = self.sample_bilinear(
+                (x as f32 + 0.5) * scale_x - 0.5,
+                (y as f32 + 0.5) * scale_y - 0.5,
+            );
+            for (out, value) in pixel.iter_mut().zip(texel) {
+                *out = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        scaled
+    }
+
+    /// Samples the image between texel centres, clamping at the edges.
+    pub fn sample_bilinear(&self, x: f32, y: f32) -> [f32; 4] {
+        let max_x = self.width - 1;
+        let max_y = self.height - 1;
+        let (fx, fy) = (x.floor(), y.floor());
+        let (tx, ty) = (x - fx, y - fy);
+        let (x0, y0) = (fx as ScreenSize, fy as ScreenSize);
+        let texel = |x: ScreenSize, y: ScreenSize| {
+            let offset = (y.clamp(0, max_y) as usize * self.width as usize + x.clamp(0, max_x) as usize)
+                * BYTES_PER_PIXEL;
+            &self.pixels()[offset..offset + BYTES_PER_PIXEL]
+        };
+        let (a, b, c, d) = (texel(x0, y0), texel(x0 + 1, y0), texel(x0, y0 + 1), texel(x0 + 1, y0 + 1));
+        [0, 1, 2, 3].map(|i| {
+            let top = a[i] as f32 + (b[i] as f32 - a[i] as f32) * tx;
+            let bottom = c[i] as f32 + (d[i] as f32 - c[i] as f32) * tx;
+            top + (bottom - top) * ty
+        })
+    }
+}
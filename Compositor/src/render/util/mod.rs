@@ -1,2 +1,3 @@
 pub mod colour;
+pub mod image;
 pub mod rect;
\ No newline at end of file
@@ -1,21 +1,27 @@
 use libprotocol::ScreenSize;
 
+/// Bits of [`Rect::get_edge_proximity`].
+pub const EDGE_RIGHT: u8 = 0b0001;
+pub const EDGE_BOTTOM: u8 = 0b0010;
+pub const EDGE_TOP: u8 = 0b0100;
+pub const EDGE_LEFT: u8 = 0b1000;
+
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Position {
     pub x: ScreenSize,
     pub y: ScreenSize,
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Size {
     pub width: ScreenSize,
     pub height: ScreenSize,
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Rect {
     pub position: Position,
     pub size: Size,
@@ -49,38 +55,64 @@ impl Rect {
         dx <= self.size.width && dy <= self.size.height && dx >= 0 && dy >= 0
     }
 
+    /// Returns a new rect with its edges pushed out by `amount` on every side.
     #[inline]
-    pub fn get_edge_proximity(&self, x: ScreenSize, y: ScreenSize, threshold: ScreenSize) -> u8 {
-        let dx = x - self.position.x;
-        let dy = y - self.position.y;
-
-        let near_right = dx >= self.size.width - threshold &&
-            dx <= self.size.width + threshold;
-        let near_bottom = dy >= self.size.height - threshold &&
-            dy <= self.size.height + threshold;
-        let near_top = dy >= 0 && dy <= threshold;
-
-        (near_right as u8) | ((near_bottom as u8) << 1) | ((near_top as u8) << 2)
-    }
-
-    // Convenience methods that use get_edge_proximity
-    #[inline]
-    pub fn is_near_bottom(&self, x: ScreenSize, y: ScreenSize, threshold: ScreenSize) -> bool {
-        (self.get_edge_proximity(x, y, threshold) & 0b010) != 0
+    pub fn expanded(&self, amount: ScreenSize) -> Rect {
+        Rect::new(
+            self.position.x - amount,
+            self.position.y - amount,
+            self.size.width + amount * 2,
+            self.size.height + amount * 2,
+        )
     }
 
+    /// Reports which edges `(x, y)` lies within `threshold` of, inside or
+    /// outside the rect, as `EDGE_*` bits. Points farther than `threshold`
+    /// from the rect are near no edge. Where the rect is too narrow to tell
+    /// two opposite edges apart, the closer one wins.
     #[inline]
-    pub fn is_near_right(&self, x: ScreenSize, y: ScreenSize, threshold: ScreenSize) -> bool {
-        (self.get_edge_proximity(x, y, threshold) & 0b001) != 0
-    }
-
-    #[inline]
-    pub fn is_near_bottom_right(&self, x: ScreenSize, y: ScreenSize, threshold: ScreenSize) -> bool {
-        (self.get_edge_proximity(x, y, threshold) & 0b011) == 0b011
+    pub fn get_edge_proximity(&self, x: ScreenSize, y: ScreenSize, threshold: ScreenSize) -> u8 {
+        if !self.expanded(threshold).contains(x, y) {
+            return 0;
+        }
+        let dx = x - self.position.x;
+        let dy = y - self.position.y;
+        let (width, height) = (self.size.width, self.size.height);
+        let horizontal = match (dx <= threshold, dx >= width - threshold) {
+            (true, true) if dx < width - dx => EDGE_LEFT,
+            (true, true) => EDGE_RIGHT,
+            (true, false) => EDGE_LEFT,
+            (false, true) => EDGE_RIGHT,
+            (false, false) => 0,
+        };
+        let vertical = match (dy <= threshold, dy >= height - threshold) {
+            (true, true) if dy < height - dy => EDGE_TOP,
+            (true, true) => EDGE_BOTTOM,
+            (true, false) => EDGE_TOP,
+            (false, true) => EDGE_BOTTOM,
+            (false, false) => 0,
+        };
+        horizontal | vertical
     }
 
+    /// Returns the rect after dragging the `EDGE_*` edges in `edges` by
+    /// `(dx, dy)`, keeping the opposite edges where they are. The size stays
+    /// between `min` and `max`.
     #[inline]
-    pub fn is_near_top(&self, x: ScreenSize, y: ScreenSize, threshold: ScreenSize) -> bool {
-        (self.get_edge_proximity(x, y, threshold) & 0b100) != 0
+    pub fn resized(&self, edges: u8, dx: ScreenSize, dy: ScreenSize, min: Size, max: Size) -> Rect {
+        let mut rect = *self;
+        if edges & EDGE_RIGHT != 0 {
+            rect.size.width = (self.size.width + dx).clamp(min.width, max.width);
+        } else if edges & EDGE_LEFT != 0 {
+            rect.size.width = (self.size.width - dx).clamp(min.width, max.width);
+            rect.position.x = self.position.x + self.size.width - rect.size.width;
+        }
+        if edges & EDGE_BOTTOM != 0 {
+            rect.size.height = (self.size.height + dy).clamp(min.height, max.height);
+        } else if edges & EDGE_TOP != 0 {
+            rect.size.height = (self.size.height - dy).clamp(min.height, max.height);
+            rect.position.y = self.position.y + self.size.height - rect.size.height;
+        }
+        rect
     }
-}
\ No newline at end of file
+}
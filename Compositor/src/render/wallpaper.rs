@@ -0,0 +1,355 @@
+//! The desktop background.
+//!
+//! A [`Wallpaper`] is an optional PNG or JPEG image laid out by a
+//! [`WallpaperMode`] over a solid or gradient [`BackgroundFill`]. It is
+//! composed on the CPU into one screen-sized image whenever the wallpaper or
+//! the screen changes, and the renderers draw that as the background layer.
+//!
+//! At startup the wallpaper comes from the environment:
+//!
+//! * `PRISM_WALLPAPER`: path of the image
+//! * `PRISM_WALLPAPER_MODE`: `fill` (the default), `fit`, `stretch`, `center` or `tile`
+//! * `PRISM_BACKGROUND`: `#rrggbb` for a solid fill, or `#rrggbb:#rrggbb` for
+//!   a gradient from top to bottom
+//!
+//! Clients on the control socket change it with `Packet::SetWallpaper`,
+//! attaching the image as an open file. Only regular files up to
+//! [`MAX_IMAGE_FILE_SIZE`] are read, so a client cannot hand over a pipe or
+//! device that never ends, and images are only decoded up to
+//! [`MAX_IMAGE_PIXELS`], since a small file can describe a huge image.
+
+use crate::render::util::image::Image;
+use crate::render::util::rect::Rect;
+use libprotocol::shm::BYTES_PER_PIXEL;
+use libprotocol::{BackgroundFill, MAX_SURFACE_SIZE, ScreenSize, WallpaperMode};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use thiserror::Error;
+
+pub const WALLPAPER_ENV: &str = "PRISM_WALLPAPER";
+pub const WALLPAPER_MODE_ENV: &str = "PRISM_WALLPAPER_MODE";
+pub const BACKGROUND_ENV: &str = "PRISM_BACKGROUND";
+
+/// Largest image file a client may attach.
+pub const MAX_IMAGE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+/// Most pixels a wallpaper may decode to: an 8K image, about 128 MiB of RGBA.
+pub const MAX_IMAGE_PIXELS: u64 = 7680 * 4320;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_SIGNATURE: &[u8] = b"\xff\xd8\xff";
+
+#[derive(Error, Debug)]
+pub enum WallpaperError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("PNG error: {0}")]
+    Png(#[from] png::DecodingError),
+
+    #[error("JPEG error: {0}")]
+    Jpeg(#[from] jpeg_decoder::Error),
+
+    #[error("not a PNG or JPEG image")]
+    UnknownFormat,
+
+    #[error("{0}x{1} is too large for a wallpaper")]
+    TooLarge(u32, u32),
+
+    #[error("not a regular file")]
+    NotAFile,
+
+    #[error("file of {0} bytes exceeds the {MAX_IMAGE_FILE_SIZE} byte limit")]
+    FileTooLarge(u64),
+}
+
+pub struct Wallpaper {
+    image: Option<Image>,
+    mode: WallpaperMode,
+    fill: BackgroundFill,
+}
+
+impl Default for Wallpaper {
+    fn default() -> Self {
+        Self::new(BackgroundFill::Solid([0, 0, 0]))
+    }
+}
+
+impl Wallpaper {
+    /// A background of just `fill`.
+    pub fn new(fill: BackgroundFill) -> Self {
+        Self {
+            image: None,
+            mode: WallpaperMode::Fill,
+            fill,
+        }
+    }
+
+    pub fn with_image(image: Image, mode: WallpaperMode, fill: BackgroundFill) -> Self {
+        Self {
+            image: Some(image),
+            mode,
+            fill,
+        }
+    }
+
+    /// Decodes the PNG or JPEG image at `path`.
+    pub fn load(path: &Path, mode: WallpaperMode, fill: BackgroundFill) -> Result<Self, WallpaperError> {
+        Self::decode(&std::fs::read(path)?, mode, fill)
+    }
+
+    /// Decodes the PNG or JPEG image in `file`, which has to have passed
+    /// [`check_image_file`].
+    pub fn from_file(mut file: File, mode: WallpaperMode, fill: BackgroundFill) -> Result<Self, WallpaperError> {
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        // The file may have grown since it was checked
+        file.take(MAX_IMAGE_FILE_SIZE + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_IMAGE_FILE_SIZE {
+            return Err(WallpaperError::FileTooLarge(data.len() as u64));
+        }
+        Self::decode(&data, mode, fill)
+    }
+
+    fn decode(data: &[u8], mode: WallpaperMode, fill: BackgroundFill) -> Result<Self, WallpaperError> {
+        let image = if data.starts_with(PNG_SIGNATURE) {
+            decode_png(data)?
+        } else if data.starts_with(JPEG_SIGNATURE) {
+            decode_jpeg(data)?
+        } else {
+            return Err(WallpaperError::UnknownFormat);
+        };
+        Ok(Self::with_image(image, mode, fill))
+    }
+
+    /// The wallpaper configured through the environment. Settings that
+    /// cannot be used are reported and left at their defaults.
+    pub fn from_env() -> Self {
+        let fill = match std::env::var(BACKGROUND_ENV) {
+            Ok(value) => parse_fill(&value).unwrap_or_else(|| {
+                eprintln!("Ignoring {}={}: expected #rrggbb or #rrggbb:#rrggbb", BACKGROUND_ENV, value);
+                Self::default().fill
+            }),
+            Err(_) => Self::default().fill,
+        };
+        let mode = match std::env::var(WALLPAPER_MODE_ENV) {
+            Ok(value) => WallpaperMode::from_name(&value).unwrap_or_else(|| {
+                eprintln!("Ignoring unknown {} {}", WALLPAPER_MODE_ENV, value);
+                WallpaperMode::Fill
+            }),
+            Err(_) => WallpaperMode::Fill,
+        };
+        let Some(path) = std::env::var_os(WALLPAPER_ENV) else {
+            return Self::new(fill);
+        };
+        Self::load(Path::new(&path), mode, fill).unwrap_or_else(|err| {
+            eprintln!("Failed to load wallpaper {}: {}", Path::new(&path).display(), err);
+            Self::new(fill)
+        })
+    }
+
+    /// Renders the background for a `width` x `height` screen, fully opaque.
+    pub fn compose(&self, width: ScreenSize, height: ScreenSize) -> Image {
+        let mut background = Image::new(width, height);
+        paint_fill(&mut background, self.fill);
+        let Some(image) = &self.image else {
+            return background;
+        };
+        let (image_width, image_height) = (image.width(), image.height());
+        match self.mode {
+            WallpaperMode::Stretch => draw_scaled(&mut background, image, &Rect::new(0, 0, width, height)),
+            WallpaperMode::Fill | WallpaperMode::Fit => {
+                let scale_x = width as f64 / image_width as f64;
+                let scale_y = height as f64 / image_height as f64;
+                let scale = if self.mode == WallpaperMode::Fill {
+                    scale_x.max(scale_y)
+                } else {
+                    scale_x.min(scale_y)
+                };
+                let scaled_width = (image_width as f64 * scale).round() as ScreenSize;
+                let scaled_height = (image_height as f64 * scale).round() as ScreenSize;
+                let rect = Rect::new(
+                    (width - scaled_width) / 2,
+                    (height - scaled_height) / 2,
+                    scaled_width,
+                    scaled_height,
+                );
+                draw_scaled(&mut background, image, &rect);
+            }
+            WallpaperMode::Center => {
+                let rect = Rect::new(
+                    (width - image_width) / 2,
+                    (height - image_height) / 2,
+                    image_width,
+                    image_height,
+                );
+                draw_scaled(&mut background, image, &rect);
+            }
+            WallpaperMode::Tile => {
+                for y in (0..height).step_by(image_height as usize) {
+                    for x in (0..width).step_by(image_width as usize) {
+                        draw_scaled(&mut background, image, &Rect::new(x, y, image_width, image_height));
+                    }
+                }
+            }
+        }
+        background
+    }
+}
+
+/// Fails unless `file` is a regular file small enough to read as an image.
+pub fn check_image_file(file: &File) -> Result<(), WallpaperError> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(WallpaperError::NotAFile);
+    }
+    if metadata.len() > MAX_IMAGE_FILE_SIZE {
+        return Err(WallpaperError::FileTooLarge(metadata.len()));
+    }
+    Ok(())
+}
+
+/// Parses `#rrggbb` as a solid fill or `#rrggbb:#rrggbb` as a gradient.
+pub fn parse_fill(value: &str) -> Option<BackgroundFill> {
+    let parse_colour = |colour: &str| -> Option<[u8; 3]> {
+        let hex = colour.strip_prefix('#')?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some([channel(0)?, channel(2)?, channel(4)?])
+    };
+    match value.split_once(':') {
+        Some((top, bottom)) => Some(BackgroundFill::VerticalGradient {
+            top: parse_colour(top)?,
+            bottom: parse_colour(bottom)?,
+        }),
+        None => Some(BackgroundFill::Solid(parse_colour(value)?)),
+    }
+}
+
+fn paint_fill(background: &mut Image, fill: BackgroundFill) {
+    let (top, bottom) = match fill {
+        BackgroundFill::Solid(colour) => (colour, colour),
+        BackgroundFill::VerticalGradient { top, bottom } => (top, bottom),
+    };
+    let stride = background.width().max(1) as usize * BYTES_PER_PIXEL;
+    let last_row = (background.height() - 1).max(1) as f32;
+    for (y, row) in background.pixels_mut().chunks_exact_mut(stride).enumerate() {
+        let t = y as f32 / last_row;
+        let colour = [0, 1, 2].map(|i| (top[i] as f32 + (bottom[i] as f32 - top[i] as f32) * t).round() as u8);
+        for pixel in row.chunks_exact_mut(BYTES_PER_PIXEL) {
+            pixel.copy_from_slice(&[colour[0], colour[1], colour[2], 255]);
+        }
+    }
+}
+
+/// Draws `image` scaled to `rect` with bilinear filtering, blending it over
+/// what is already there. Drawn at its own size it is copied exactly.
+fn draw_scaled(background: &mut Image, image: &Image, rect: &Rect) {
+    if rect.size.width <= 0 || rect.size.height <= 0 {
+        return;
+    }
+    let x0 = rect.position.x.max(0);
+    let y0 = rect.position.y.max(0);
+    let x1 = (rect.position.x + rect.size.width).min(background.width());
+    let y1 = (rect.position.y + rect.size.height).min(background.height());
+    let scale_x = image.width() as f32 / rect.size.width as f32;
+    let scale_y = image.height() as f32 / rect.size.height as f32;
+    let stride = background.width() as usize * BYTES_PER_PIXEL;
+    let pixels = background.pixels_mut();
+    for y in y0..y1 {
+        let v = (y - rect.position.y) as f32 + 0.5;
+        let source_y = v * scale_y - 0.5;
+        for x in x0..x1 {
+            let u = (x - rect.position.x) as f32 + 0.5;
+            let texel = image.sample_bilinear(u * scale_x - 0.5, source_y);
+            let offset = y as usize * stride + x as usize * BYTES_PER_PIXEL;
+            let pixel = &mut pixels[offset..offset + BYTES_PER_PIXEL];
+            let alpha = texel[3] / 255.0;
+            for channel in 0..3 {
+                let out = texel[channel] * alpha + pixel[channel] as f32 * (1.0 - alpha);
+                pixel[channel] = out.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), WallpaperError> {
+    if width == 0
+        || height == 0
+        || width > MAX_SURFACE_SIZE as u32
+        || height > MAX_SURFACE_SIZE as u32
+        || width as u64 * height as u64 > MAX_IMAGE_PIXELS
+    {
+        return Err(WallpaperError::TooLarge(width, height));
+    }
+    Ok(())
+}
+
+/// Decodes a PNG of any colour type and bit depth to 8-bit RGBA.
+fn decode_png(data: &[u8]) -> Result<Image, WallpaperError> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let (width, height) = reader.info().size();
+    check_size(width, height)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        // Palettes are expanded to RGB(A) by `normalize_to_color8`
+        png::ColorType::Grayscale | png::ColorType::Indexed => {
+            buf.iter().flat_map(|&l| [l, l, l, 255]).collect()
+        }
+    };
+    Ok(Image::from_rgba(width as ScreenSize, height as ScreenSize, pixels))
+}
+
+/// Decodes a baseline or progressive JPEG to 8-bit RGBA.
+fn decode_jpeg(data: &[u8]) -> Result<Image, WallpaperError> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    decoder.read_info()?;
+    let info = decoder.info().expect("JPEG info is read");
+    check_size(info.width as u32, info.height as u32)?;
+    let buf = decoder.decode()?;
+    let pixels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => {
+            buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect()
+        }
+        jpeg_decoder::PixelFormat::L8 => buf.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+        // Big-endian samples; the high byte is enough for display
+        jpeg_decoder::PixelFormat::L16 => buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], 255]).collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => buf
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let k = p[3] as u32;
+                let [r, g, b] = [p[0], p[1], p[2]].map(|c| ((255 - c as u32) * (255 - k) / 255) as u8);
+                [r, g, b, 255]
+            })
+            .collect(),
+    };
+    Ok(Image::from_rgba(info.width as ScreenSize, info.height as ScreenSize, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_over_pixel_budget_are_refused() {
+        assert!(check_size(7680, 4320).is_ok());
+        assert!(matches!(check_size(8192, 8192), Err(WallpaperError::TooLarge(8192, 8192))));
+        assert!(matches!(check_size(8192, 4096), Err(WallpaperError::TooLarge(..))));
+    }
+
+    #[test]
+    fn images_over_surface_size_are_refused() {
+        let too_wide = MAX_SURFACE_SIZE as u32 + 1;
+        assert!(matches!(check_size(too_wide, 1), Err(WallpaperError::TooLarge(..))));
+        assert!(matches!(check_size(0, 1), Err(WallpaperError::TooLarge(..))));
+    }
+}
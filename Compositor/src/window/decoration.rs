@@ -0,0 +1,108 @@
+//! Server-side title bar buttons.
+//!
+//! Every titled window gets minimize, maximize and close buttons at the right
+//! end of its title bar, with the title text between the icon and the
+//! buttons. The geometry lives here so the renderers and the pointer
+//! hit-testing in `DisplayServer` agree on where everything is.
+
+use crate::render::util::colour::Colour;
+use crate::render::util::rect::Rect;
+use crate::window::window::{TITLE_BAR_HEIGHT, WINDOW_PADDING, Window};
+use libprotocol::ScreenSize;
+
+/// Edge length of a button, matching the icons it draws.
+pub const BUTTON_SIZE: ScreenSize = 20;
+/// Gap between buttons and around the row of buttons.
+const BUTTON_MARGIN: ScreenSize = (TITLE_BAR_HEIGHT - BUTTON_SIZE) / 2;
+/// Edge length of the window icon at the left end of the title bar.
+pub const ICON_SIZE: ScreenSize = BUTTON_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleButton {
+    Minimize,
+    Maximize,
+    Close,
+}
+
+impl TitleButton {
+    /// Left to right, as drawn.
+    pub const ALL: [TitleButton; 3] = [TitleButton::Minimize, TitleButton::Maximize, TitleButton::Close];
+
+    /// Icon under the shared data directory.
+    pub fn icon_path(self) -> &'static str {
+        match self {
+            TitleButton::Minimize => "icons/Default/window/minimize.png",
+            TitleButton::Maximize => "icons/Default/window/maximize.png",
+            TitleButton::Close => "icons/Default/window/close.png",
+        }
+    }
+
+    /// Where the button sits on screen for a window with render rect `window`.
+    pub fn rect(self, window: &Rect) -> Rect {
+        // Counted from the right edge of the frame
+        let slot = match self {
+            TitleButton::Close => 1,
+            TitleButton::Maximize => 2,
+            TitleButton::Minimize => 3,
+        };
+        let right = window.position.x + window.size.width + WINDOW_PADDING * 2;
+        Rect::new(
+            right - slot * (BUTTON_SIZE + BUTTON_MARGIN),
+            window.position.y + BUTTON_MARGIN,
+            BUTTON_SIZE,
+            BUTTON_SIZE,
+        )
+    }
+
+    /// Backdrop drawn behind the icon, if the button is highlighted.
+    pub fn highlight(self, state: ButtonState) -> Colour {
+        match (self, state) {
+            (TitleButton::Close, ButtonState::Hovered) => Colour::new(196, 43, 28, 200),
+            (TitleButton::Close, ButtonState::Pressed) => Colour::new(148, 32, 21, 230),
+            (_, ButtonState::Hovered) => Colour::grayscale_alpha(255, 48),
+            (_, ButtonState::Pressed) => Colour::grayscale_alpha(255, 96),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonState {
+    Hovered,
+    Pressed,
+}
+
+/// Where the icon of a window at `window` is drawn.
+pub fn icon_rect(window: &Rect) -> Rect {
+    Rect::new(
+        window.position.x + BUTTON_MARGIN,
+        window.position.y + BUTTON_MARGIN,
+        ICON_SIZE,
+        ICON_SIZE,
+    )
+}
+
+/// Space the title text may use in the title bar of a window.
+///
+/// Starts after the icon slot and ends one margin before the leftmost button.
+pub fn title_rect(window: &Window) -> Rect {
+    let rect = window.get_render_rect();
+    let left = rect.position.x
+        + if window.has_icon() {
+            TITLE_BAR_HEIGHT
+        } else {
+            BUTTON_MARGIN * 2
+        };
+    let right = TitleButton::Minimize.rect(rect).position.x - BUTTON_MARGIN;
+    Rect::new(left, rect.position.y, (right - left).max(0), TITLE_BAR_HEIGHT)
+}
+
+/// Finds the title bar button of `window` under the pointer.
+pub fn button_at(window: &Window, x: ScreenSize, y: ScreenSize) -> Option<TitleButton> {
+    if !window.has_decorations() || window.is_minimized() {
+        return None;
+    }
+    let rect = window.get_render_rect();
+    TitleButton::ALL
+        .into_iter()
+        .find(|button| button.rect(rect).contains(x, y))
+}
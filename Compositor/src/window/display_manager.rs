@@ -1,20 +1,90 @@
+use crate::common::cursor::{self, ClientCursor, CursorTheme};
 use crate::common::mouse::Mouse;
-use crate::render::Renderer;
-use crate::window::window::{Window, WINDOW_PADDING};
-use std::collections::HashMap;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use libprotocol::ScreenSize;
+use crate::net::handle::EventSender;
+use crate::render::RenderBackend;
+use crate::render::wallpaper::Wallpaper;
+use crate::window::decoration::{self, ButtonState, TitleButton};
+use crate::window::input::InputRouter;
+use crate::window::registry::WindowRegistry;
+use crate::window::stack::WindowStack;
+use crate::window::window::{Window, TITLE_BAR_HEIGHT, WINDOW_PADDING};
+use libprotocol::{CursorShape, Event, KeyCode, Modifiers, MouseButton, ScreenSize, WindowState};
 use crate::render::api::texture::Texture;
-use crate::render::util::rect::{Position, Rect, Size};
+use crate::render::util::image::Image;
+use crate::render::util::rect::{EDGE_BOTTOM, EDGE_LEFT, EDGE_RIGHT, EDGE_TOP, Position, Rect, Size};
+
+/// How far from a resizable edge the pointer still grabs it.
+const RESIZE_BORDER: ScreenSize = 7;
+
+/// What dragging a window's frame with the left button does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameAction {
+    /// Moves the window, from its title bar
+    Move,
+    /// Resizes the window from the `EDGE_*` edges
+    Resize(u8),
+}
+
+impl FrameAction {
+    fn cursor(self) -> CursorShape {
+        match self {
+            FrameAction::Move => CursorShape::Move,
+            FrameAction::Resize(edges) => {
+                match (edges & (EDGE_LEFT | EDGE_RIGHT), edges & (EDGE_TOP | EDGE_BOTTOM)) {
+                    (0, _) => CursorShape::NsResize,
+                    (_, 0) => CursorShape::EwResize,
+                    (EDGE_LEFT, EDGE_TOP) | (EDGE_RIGHT, EDGE_BOTTOM) => CursorShape::NwseResize,
+                    _ => CursorShape::NeswResize,
+                }
+            }
+        }
+    }
+}
+
+/// A move or resize in progress. The window is laid out from where the
+/// pointer and the window were when the drag started, so the edges not being
+/// dragged stay exactly where they were.
+#[derive(Debug, Clone, Copy)]
+struct FrameDrag {
+    window_id: u64,
+    action: FrameAction,
+    pointer: (ScreenSize, ScreenSize),
+    rect: Rect,
+}
+
+/// Where the cursor image comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PointerCursor {
+    /// A theme cursor, for the desktop and window decorations
+    Themed(CursorShape),
+    /// Whatever the client set for its window under the pointer
+    Client(u64),
+}
 
 pub struct DisplayServer {
-    windows: HashMap<u64,Window>,
+    windows: WindowStack,
+    /// Window receiving keyboard input, which is also raised
+    focused: Option<u64>,
+    registry: WindowRegistry,
+    input: InputRouter,
     mouse: Mouse,
+    cursors: CursorTheme,
+    wallpaper: Wallpaper,
+    /// Cursor for what is under the pointer
+    cursor: PointerCursor,
+    /// Title bar button the left mouse button went down on
+    pressed_title_button: Option<(u64, TitleButton)>,
+    /// Window being moved or resized with the left mouse button
+    drag: Option<FrameDrag>,
+    /// Serial of the last `Configure` sent to any window
+    configure_serial: u32,
     low_state_mode: bool,
     is_mouse_dirty: bool,
     is_window_dirty: bool,
     is_background_dirty: bool,
-    renderer: Option<Renderer>,
+    renderer: Option<Box<dyn RenderBackend>>,
+    // Content textures of closed windows, freed on the next tick where GL is current
+    retired_textures: Vec<Texture>,
     width: ScreenSize,
     height: ScreenSize,
 }
@@ -22,13 +92,23 @@ pub struct DisplayServer {
 impl DisplayServer {
     pub fn new() -> Self {
         Self {
-            windows: HashMap::new(),
+            windows: WindowStack::new(),
+            focused: None,
+            registry: WindowRegistry::new(),
+            input: InputRouter::new(),
             mouse: Mouse::new(),
+            cursors: CursorTheme::load(cursor::DEFAULT_THEME),
+            wallpaper: Wallpaper::default(),
+            cursor: PointerCursor::Themed(CursorShape::Default),
+            pressed_title_button: None,
+            drag: None,
+            configure_serial: 0,
             low_state_mode: false,
             is_window_dirty: false,
             is_mouse_dirty: false,
             is_background_dirty: false,
             renderer: None,
+            retired_textures: Vec::new(),
             width:0,
             height:0,
         }
@@ -40,9 +120,27 @@ impl DisplayServer {
         Rect::new(width,height,window_width,window_height)
     }
     
+    pub fn set_event_sender(&mut self, events: EventSender) {
+        self.input.set_event_sender(events);
+    }
+
+    /// Handle for sending packets to clients, once the server is running.
+    pub fn event_sender(&self) -> Option<EventSender> {
+        self.input.event_sender().cloned()
+    }
+
+    pub fn set_wallpaper(&mut self, wallpaper: Wallpaper) {
+        self.wallpaper = wallpaper;
+        self.is_background_dirty = true;
+    }
+
     pub fn update_mouse_pos(&mut self, x:ScreenSize, y:ScreenSize) {
         self.is_mouse_dirty = true;
         self.mouse.add_position(x, y);
+        self.update_title_buttons();
+        self.input
+            .pointer_motion(&self.windows, &self.registry, self.mouse.get_x(), self.mouse.get_y());
+        self.update_cursor();
     }
     
     pub fn update_mouse_wheel_delta(&mut self, x:f32, y:f32) {
@@ -62,16 +160,445 @@ impl DisplayServer {
             }
             _ => {}
         }
+        if state {
+            self.focus_window(self.window_at_pointer());
+        }
+        if button == 0 {
+            self.click_title_button(state);
+            self.drag = match self.frame_action_at_pointer() {
+                Some((window_id, action)) if state => Some(FrameDrag {
+                    window_id,
+                    action,
+                    pointer: (self.mouse.get_x(), self.mouse.get_y()),
+                    rect: *self.windows[&window_id].get_render_rect(),
+                }),
+                _ => None,
+            };
+        }
+        let button = match button {
+            0 => MouseButton::Left,
+            1 => MouseButton::Right,
+            2 => MouseButton::Middle,
+            other => MouseButton::Other(other),
+        };
+        self.input.pointer_button(
+            &self.windows,
+            &self.registry,
+            button,
+            state,
+            self.mouse.get_x(),
+            self.mouse.get_y(),
+        );
+        self.update_cursor();
+    }
+
+    /// Topmost window under the pointer, decorations and resize border
+    /// included.
+    fn window_at_pointer(&self) -> Option<u64> {
+        let (x, y) = (self.mouse.get_x(), self.mouse.get_y());
+        self.windows
+            .iter_top_down()
+            .find(|(_, window)| !window.is_minimized() && Self::grab_area(window).contains(x, y))
+            .map(|(window_id, _)| window_id)
+    }
+
+    /// Where the pointer counts as being on a window: its frame, plus the
+    /// border its edges can be grabbed from if it is resizable and does not
+    /// fill the screen.
+    fn grab_area(window: &Window) -> Rect {
+        let frame = window.get_frame_rect();
+        if !window.is_resizable() || window.is_maximized() || window.is_fullscreen() {
+            frame
+        } else {
+            frame.expanded(RESIZE_BORDER)
+        }
+    }
+
+    /// What a left button press at the pointer would do to the window
+    /// under it, if it lands on the window's frame rather than on a title bar
+    /// button or the client area.
+    fn frame_action_at_pointer(&self) -> Option<(u64, FrameAction)> {
+        let (x, y) = (self.mouse.get_x(), self.mouse.get_y());
+        if self.title_button_at_pointer().is_some() {
+            return None;
+        }
+        let window_id = self.window_at_pointer()?;
+        let window = &self.windows[&window_id];
+        let fills_screen = window.is_maximized() || window.is_fullscreen();
+        if fills_screen || window.get_content_rect().contains(x, y) {
+            return None;
+        }
+        let frame = window.get_frame_rect();
+        let edges = frame.get_edge_proximity(x, y, RESIZE_BORDER);
+        if edges != 0 && window.is_resizable() {
+            return Some((window_id, FrameAction::Resize(edges)));
+        }
+        let title_bar = Rect::new(frame.position.x, frame.position.y, frame.size.width, TITLE_BAR_HEIGHT);
+        (window.has_decorations() && title_bar.contains(x, y)).then_some((window_id, FrameAction::Move))
+    }
+
+    /// Window and title bar button under the pointer, if any.
+    fn title_button_at_pointer(&self) -> Option<(u64, TitleButton)> {
+        let (x, y) = (self.mouse.get_x(), self.mouse.get_y());
+        let window_id = self.window_at_pointer()?;
+        let button = decoration::button_at(self.windows.get(&window_id)?, x, y)?;
+        Some((window_id, button))
+    }
+
+    /// Raises a window and gives it keyboard focus, telling both the client
+    /// losing focus and the one gaining it. `None` leaves no window focused.
+    fn focus_window(&mut self, window_id: Option<u64>) {
+        if let Some(window_id) = window_id
+            && self.windows.raise(window_id)
+        {
+            self.is_window_dirty = true;
+        }
+        if window_id == self.focused {
+            return;
+        }
+        if let Some(old) = self.focused.take()
+            && let Some(window) = self.windows.get_mut(&old)
+        {
+            window.set_active(false);
+            self.input.send(&self.registry, old, Event::FocusOut);
+        }
+        if let Some(new) = window_id
+            && let Some(window) = self.windows.get_mut(&new)
+        {
+            window.set_active(true);
+            self.focused = Some(new);
+            self.input.send(&self.registry, new, Event::FocusIn);
+        }
+        self.input.set_focused(self.focused);
+    }
+
+    /// Focuses the topmost window that is not minimized, if any.
+    fn focus_topmost(&mut self) {
+        let topmost = self
+            .windows
+            .iter_top_down()
+            .find(|(_, window)| !window.is_minimized())
+            .map(|(window_id, _)| window_id);
+        self.focus_window(topmost);
+    }
+
+    /// Sets the cursor a client wants over one of its windows.
+    pub fn set_window_cursor(&mut self, window_id: &u64, cursor: ClientCursor) {
+        if let Some(window) = self.windows.get_mut(window_id) {
+            window.set_cursor(cursor);
+            // The pointer may already be over it
+            self.is_mouse_dirty = true;
+        }
+    }
+
+    /// Sets the size range a window can be resized within, shrinking or
+    /// growing it right away if it is outside the new range.
+    pub fn set_window_size_limits(&mut self, window_id: &u64, min: Size, max: Option<Size>) {
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.set_size_limits(min, max);
+        if window.is_maximized() || window.is_fullscreen() || window.is_minimized() {
+            return;
+        }
+        let (min, max) = (window.get_min_size(), window.get_max_size());
+        let size = window.get_size();
+        let width = size.width.clamp(min.width, max.width);
+        let height = size.height.clamp(min.height, max.height);
+        if (width, height) != (size.width, size.height) {
+            window.get_mut_render_rect().set_size(width, height);
+            self.configure(*window_id);
+        }
+    }
+
+    /// Changes the text in a window's title bar.
+    pub fn set_window_title(&mut self, window_id: &u64, title: Option<String>) {
+        if let Some(window) = self.windows.get_mut(window_id) {
+            window.update_title(title);
+            self.is_window_dirty = true;
+        }
+    }
+
+    /// Changes the ID of the application a window belongs to.
+    pub fn set_window_app_id(&mut self, window_id: &u64, app_id: Option<String>) {
+        if let Some(window) = self.windows.get_mut(window_id) {
+            window.set_app_id(app_id);
+            self.is_window_dirty = true;
+        }
+    }
+
+    /// Shows `icon` in a window's title bar, scaled to fit, or removes the
+    /// icon with `None`.
+    pub fn set_window_icon(&mut self, window_id: &u64, icon: Option<Image>) {
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        let icon = icon.map(|icon| icon.scaled(decoration::ICON_SIZE, decoration::ICON_SIZE));
+        if let Some(texture) = window.set_icon(icon) {
+            self.retired_textures.push(texture);
+        }
+        self.is_window_dirty = true;
+    }
+
+    /// Sets whether the user can resize a window by dragging its edges.
+    pub fn set_window_resizable(&mut self, window_id: &u64, resizable: bool) {
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.set_resizable(resizable);
+        let resizing = self
+            .drag
+            .is_some_and(|drag| drag.window_id == *window_id && drag.action != FrameAction::Move);
+        if !resizable && resizing {
+            self.drag = None;
+        }
+        self.is_window_dirty = true;
+        self.update_cursor();
+    }
+
+    /// Shows or hides a window's title bar and frame. The client area stays
+    /// where it is.
+    pub fn set_window_decorations(&mut self, window_id: &u64, decorated: bool) {
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.set_title_bar(decorated);
+        self.end_drag(*window_id);
+        self.is_window_dirty = true;
+        self.update_title_buttons();
+        self.update_cursor();
+    }
+
+    /// Fills the screen with a window, keeping its decorations, and focuses
+    /// it. A fullscreen window stays fullscreen and goes back to being
+    /// maximized when it leaves fullscreen.
+    pub fn maximize_window(&mut self, window_id: &u64) {
+        let rect = self.maximized_rect();
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.maximize();
+        if !window.is_fullscreen() {
+            *window.get_mut_render_rect() = rect;
+        }
+        self.end_drag(*window_id);
+        self.focus_window(Some(*window_id));
+        self.configure(*window_id);
+    }
+
+    /// Puts a maximized window back where it was before.
+    pub fn unmaximize_window(&mut self, window_id: &u64) {
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.unmaximize();
+        self.end_drag(*window_id);
+        self.configure(*window_id);
+    }
+
+    /// Hides a window, handing focus to the topmost one still shown.
+    pub fn minimize_window(&mut self, window_id: &u64) {
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.minimize();
+        self.end_drag(*window_id);
+        if self.focused == Some(*window_id) {
+            self.focus_topmost();
+        }
+        self.configure(*window_id);
+    }
+
+    /// Covers the screen with a window's client area and focuses it.
+    pub fn fullscreen_window(&mut self, window_id: &u64) {
+        let rect = Rect::new(0, 0, self.width, self.height);
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.fullscreen();
+        *window.get_mut_render_rect() = rect;
+        self.end_drag(*window_id);
+        self.focus_window(Some(*window_id));
+        self.configure(*window_id);
+    }
+
+    /// Puts a fullscreen window back where it was before, or back to
+    /// maximized if it was maximized underneath.
+    pub fn unfullscreen_window(&mut self, window_id: &u64) {
+        let rect = self.maximized_rect();
+        let Some(window) = self.windows.get_mut(window_id) else {
+            return;
+        };
+        window.unfullscreen();
+        if window.is_maximized() {
+            *window.get_mut_render_rect() = rect;
+        }
+        self.end_drag(*window_id);
+        self.configure(*window_id);
+    }
+
+    /// Where a maximized window goes: its frame covering the screen.
+    fn maximized_rect(&self) -> Rect {
+        Rect::new(
+            0,
+            0,
+            self.width - WINDOW_PADDING * 2,
+            self.height - TITLE_BAR_HEIGHT - WINDOW_PADDING,
+        )
+    }
+
+    /// Stops moving or resizing a window whose geometry was just replaced.
+    fn end_drag(&mut self, window_id: u64) {
+        if self.drag.is_some_and(|drag| drag.window_id == window_id) {
+            self.drag = None;
+        }
+    }
+
+    /// Redraws a window that changed size or state and tells its client.
+    fn configure(&mut self, window_id: u64) {
+        let Some(window) = self.windows.get_mut(&window_id) else {
+            return;
+        };
+        self.configure_serial = self.configure_serial.wrapping_add(1);
+        let serial = self.configure_serial;
+        let size = window.get_size();
+        window.push_configure(serial, size);
+        let state = WindowState {
+            maximized: window.is_maximized(),
+            minimized: window.is_minimized(),
+            fullscreen: window.is_fullscreen(),
+        };
+        self.is_window_dirty = true;
+        self.input.send(
+            &self.registry,
+            window_id,
+            Event::Configure {
+                serial,
+                width: size.width,
+                height: size.height,
+                state,
+            },
+        );
+    }
+
+    fn update_cursor(&mut self) {
+        let cursor = self.pointer_cursor();
+        if cursor != self.cursor {
+            self.cursor = cursor;
+            self.is_mouse_dirty = true;
+        }
+    }
+
+    /// Picks the cursor for what is under the pointer: resize arrows on the
+    /// edges `update` resizes from, a move cursor on the title bar and the
+    /// client's own cursor over its client area.
+    fn pointer_cursor(&self) -> PointerCursor {
+        match self.decoration_cursor() {
+            CursorShape::Default => match self.input.pointer_window() {
+                Some(window_id) => PointerCursor::Client(window_id),
+                None => PointerCursor::Themed(CursorShape::Default),
+            },
+            shape => PointerCursor::Themed(shape),
+        }
+    }
+
+    /// Cursor for the window decorations under the pointer, or the default
+    /// one anywhere else. A drag keeps its cursor until it ends.
+    fn decoration_cursor(&self) -> CursorShape {
+        if let Some(drag) = &self.drag {
+            return drag.action.cursor();
+        }
+        if self.pressed_title_button.is_some() {
+            return CursorShape::Default;
+        }
+        self.frame_action_at_pointer()
+            .map_or(CursorShape::Default, |(_, action)| action.cursor())
+    }
+
+    /// Highlights the title bar button under the pointer, or the pressed one
+    /// while the pointer is still on it.
+    fn update_title_buttons(&mut self) {
+        let hit = self.title_button_at_pointer();
+        for (window_id, window) in self.windows.iter_mut() {
+            let state = match self.pressed_title_button {
+                Some(pressed) if pressed.0 == window_id => {
+                    (hit == Some(pressed)).then_some((pressed.1, ButtonState::Pressed))
+                }
+                Some(_) => None,
+                None => hit
+                    .filter(|&(hit_id, _)| hit_id == window_id)
+                    .map(|(_, button)| (button, ButtonState::Hovered)),
+            };
+            if window.set_title_button(state) {
+                self.is_window_dirty = true;
+            }
+        }
+    }
+
+    /// Presses or releases a title bar button with the left mouse button.
+    ///
+    /// A button fires when released over the same button it was pressed on.
+    fn click_title_button(&mut self, pressed: bool) {
+        let hit = self.title_button_at_pointer();
+        if pressed {
+            self.pressed_title_button = hit;
+        } else if let Some((window_id, button)) = self.pressed_title_button.take()
+            && hit == Some((window_id, button))
+        {
+            self.activate_title_button(window_id, button);
+        }
+        self.update_title_buttons();
+    }
+
+    fn activate_title_button(&mut self, window_id: u64, button: TitleButton) {
+        match button {
+            // The window stays until its client answers with `Close`, so
+            // requests it already sent still find it
+            TitleButton::Close => self.input.send(&self.registry, window_id, Event::Closed),
+            TitleButton::Minimize => self.minimize_window(&window_id),
+            TitleButton::Maximize => self.toggle_maximized(window_id),
+        }
+        self.is_window_dirty = true;
+    }
+
+    /// Fills the screen with a window, or puts a maximized one back.
+    fn toggle_maximized(&mut self, window_id: u64) {
+        let Some(window) = self.windows.get(&window_id) else {
+            return;
+        };
+        if window.is_maximized() {
+            self.unmaximize_window(&window_id);
+        } else {
+            self.maximize_window(&window_id);
+        }
+    }
+
+    pub fn update_key_state(&mut self, key: KeyCode, modifiers: Modifiers, pressed: bool) {
+        self.input.key(&self.registry, key, modifiers, pressed);
     }
     
-    pub fn add_window(&mut self, mut window: Window) -> u64 {
-        window.set_active(true);
-        let mut hasher = DefaultHasher::new();
-        window.get_title().hash(&mut hasher);
-        let hash = hasher.finish();
-        self.windows.insert(hash,window);
+    /// Adds a window on top of the others and focuses it.
+    pub fn add_window(&mut self, window: Window, owner: u64) -> u64 {
+        let window_id = self.registry.allocate(owner);
+        self.windows.insert(window_id, window);
+        self.focus_window(Some(window_id));
         self.is_window_dirty = true;
-        hash
+        window_id
+    }
+
+    /// Checks if a connection has created windows that are still open.
+    pub fn has_windows(&self, connection_id: u64) -> bool {
+        self.registry.has_windows(connection_id)
+    }
+
+    /// Returns the connection that created a window.
+    pub fn get_window_owner(&self, window_id: u64) -> Option<u64> {
+        self.registry.owner(window_id)
+    }
+
+    /// Checks if a connection owned a window that was closed recently.
+    pub fn used_to_own_window(&self, connection_id: u64, window_id: u64) -> bool {
+        self.registry.used_to_own(connection_id, window_id)
     }
 
     pub fn get_window_size(&self,window_id: &u64) -> Size {
@@ -80,8 +607,23 @@ impl DisplayServer {
         rect.size
     }
     
-    pub fn update_window_frame_buffer(&mut self,_window_id: &u64,_frame_buffer_data:&Vec<u8>) {
-        self.is_window_dirty = true;
+    /// Shows a newly painted frame, unless the window is waiting for one at
+    /// the size its client acknowledged.
+    pub fn update_window_frame_buffer(&mut self, window_id: &u64, frame: Image) {
+        if let Some(window) = self.windows.get_mut(window_id)
+            && window.accepts_frame(frame.width(), frame.height())
+        {
+            window.set_frame(frame);
+            self.is_window_dirty = true;
+        }
+    }
+
+    /// Notes that a window's client will paint at the size of the configure
+    /// with `serial`. Returns false if no such configure is pending.
+    pub fn ack_window_configure(&mut self, window_id: &u64, serial: u32) -> bool {
+        self.windows
+            .get_mut(window_id)
+            .is_some_and(|window| window.ack_configure(serial))
     }
     
     pub fn get_window_pos(&self,window_id: &u64) -> Position {
@@ -91,14 +633,35 @@ impl DisplayServer {
     }
 
     pub fn remove_window(&mut self, window_id: &u64) {
-        self.windows.remove(&window_id);
+        if let Some(mut window) = self.windows.remove(window_id) {
+            self.retired_textures.extend(window.take_content());
+            self.retired_textures.extend(window.take_icon_texture());
+        }
+        self.registry.release(*window_id);
+        self.input.forget_window(*window_id);
+        if self.focused == Some(*window_id) {
+            self.focused = None;
+            self.focus_topmost();
+        }
         self.is_window_dirty = true;
+        self.update_cursor();
+    }
+
+    /// Closes every window owned by a connection that went away.
+    pub fn remove_connection_windows(&mut self, connection_id: u64) {
+        for window_id in self.registry.release_connection(connection_id) {
+            self.remove_window(&window_id);
+        }
     }
     
-    pub fn setup_renderer(&mut self,width: ScreenSize,height: ScreenSize) {
-        self.renderer = Some(Renderer::new(width, height));
+    pub fn setup_renderer(&mut self, renderer: Box<dyn RenderBackend>, width: ScreenSize, height: ScreenSize) {
+        self.renderer = Some(renderer);
         self.width = width;
         self.height = height;
+        // A new renderer starts with empty layers
+        self.is_mouse_dirty = true;
+        self.is_window_dirty = true;
+        self.is_background_dirty = true;
     }
     
     pub fn tick(&mut self) {
@@ -107,53 +670,87 @@ impl DisplayServer {
         }
         self.update();
         let renderer = self.renderer.as_mut().expect("Render not set up");
+        for mut texture in self.retired_textures.drain(..) {
+            texture.cleanup();
+        }
         if self.is_mouse_dirty {
-            renderer.rerender_mouse(self.mouse.get_x(),self.mouse.get_y());
+            let cursor = match self.cursor {
+                PointerCursor::Themed(shape) => self.cursors.get(shape),
+                PointerCursor::Client(window_id) => {
+                    match self.windows.get(&window_id).and_then(Window::get_cursor) {
+                        Some(ClientCursor::Shape(shape)) => self.cursors.get(*shape),
+                        Some(ClientCursor::Image(cursor)) => cursor,
+                        None => self.cursors.get(CursorShape::Default),
+                    }
+                }
+            };
+            renderer.rerender_mouse(self.mouse.get_x(), self.mouse.get_y(), cursor);
             self.is_mouse_dirty = false;
         }
         if self.is_window_dirty {
-            renderer.rerender_windows(&self.windows);
+            renderer.rerender_windows(&mut self.windows);
             self.is_window_dirty = false;
         }
         if self.is_background_dirty {
-            renderer.rerender_background(&Texture::not_owned(0,0,0));
+            renderer.rerender_background(&self.wallpaper.compose(self.width, self.height));
             self.is_background_dirty = false;
         }
         renderer.render();
     }
 
+    /// Copies the last frame the renderer composed, if one is set up.
+    pub fn read_frame(&mut self) -> Option<Image> {
+        self.renderer.as_mut().map(|renderer| renderer.read_frame())
+    }
+
+    /// Applies the frame drag in progress to its window.
     fn update(&mut self) {
-        for (_key,window) in &mut self.windows {
-            if window.is_active() {
-                let rect = window.get_mut_render_rect();
-                // resize or move the window
-                if self.mouse.is_left_button_pressed() {
-                    if rect.is_near_bottom_right(self.mouse.get_x(), self.mouse.get_y(), 7) {
-                        rect.size.width = (rect.size.width + self.mouse.get_rel_x()).max(200);
-                        rect.size.height = (rect.size.height + self.mouse.get_rel_y()).max(200);
-                        self.is_window_dirty = true;
-                    }
-                    if rect.is_near_right(self.mouse.get_x(), self.mouse.get_y(), 7) {
-                        rect.size.width = (rect.size.width + self.mouse.get_rel_x()).max(200);
-                        self.is_window_dirty = true;
-                    }
-                    if rect.is_near_bottom(self.mouse.get_x(), self.mouse.get_y(), 7) {
-                        rect.size.height = (rect.size.height + self.mouse.get_rel_y()).max(200);
-                        self.is_window_dirty = true;
-                    }
-                    if rect.is_near_top(self.mouse.get_x(), self.mouse.get_y(), 30 + WINDOW_PADDING) {
-                        rect.position.x = (rect.position.x + self.mouse.get_rel_x()).clamp(0, self.width - rect.size.width);
-                        rect.position.y = (rect.position.y + self.mouse.get_rel_y()).clamp(0, self.height - rect.size.height);
-                        self.is_window_dirty = true;
-                    }
-                }
-                break;
+        let Some(drag) = self.drag else {
+            return;
+        };
+        let Some(window) = self.windows.get_mut(&drag.window_id) else {
+            self.drag = None;
+            return;
+        };
+        let dx = self.mouse.get_x() - drag.pointer.0;
+        let dy = self.mouse.get_y() - drag.pointer.1;
+        let start = drag.rect;
+        let rect = match drag.action {
+            FrameAction::Move => {
+                // Keep the whole frame on screen
+                let max_x = self.width - start.size.width - WINDOW_PADDING * 2;
+                let max_y = self.height - start.size.height - TITLE_BAR_HEIGHT - WINDOW_PADDING;
+                Rect::new(
+                    (start.position.x + dx).min(max_x).max(0),
+                    (start.position.y + dy).min(max_y).max(0),
+                    start.size.width,
+                    start.size.height,
+                )
+            }
+            FrameAction::Resize(edges) => {
+                start.resized(edges, dx, dy, window.get_min_size(), window.get_max_size())
             }
+        };
+        if rect == *window.get_render_rect() {
+            return;
+        }
+        let resized = rect.size != window.get_size();
+        *window.get_mut_render_rect() = rect;
+        self.is_window_dirty = true;
+        if resized {
+            self.configure(drag.window_id);
         }
     }
-    
+
     pub fn cleanup(&mut self) {
+        for window in self.windows.values_mut() {
+            self.retired_textures.extend(window.take_content());
+            self.retired_textures.extend(window.take_icon_texture());
+        }
         if let Some(renderer) = self.renderer.as_mut() {
+            for mut texture in self.retired_textures.drain(..) {
+                texture.cleanup();
+            }
             renderer.cleanup();
         }
         self.windows.clear();
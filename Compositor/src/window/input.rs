@@ -0,0 +1,165 @@
+//! Routes pointer and keyboard input to client windows.
+//!
+//! Pointer events go to the topmost window under the pointer while it is over
+//! that window's client area, in coordinates relative to that area. Pressing a
+//! button grabs the pointer for the window under it until every button is
+//! released again, so drags keep reaching the window that started them.
+//! Keyboard events go to the focused window, which `DisplayServer` picks.
+
+use crate::net::handle::EventSender;
+use crate::window::registry::WindowRegistry;
+use crate::window::stack::WindowStack;
+use libprotocol::{Event, KeyCode, Modifiers, MouseButton, Packet, ScreenSize};
+
+pub struct InputRouter {
+    events: Option<EventSender>,
+    /// Window whose client area the pointer is over
+    hovered: Option<u64>,
+    /// Window receiving keyboard input
+    focused: Option<u64>,
+    /// Window holding the pointer grab while buttons are down
+    grab: Option<u64>,
+    buttons_down: u32,
+}
+
+impl InputRouter {
+    pub fn new() -> Self {
+        Self {
+            events: None,
+            hovered: None,
+            focused: None,
+            grab: None,
+            buttons_down: 0,
+        }
+    }
+
+    pub fn set_event_sender(&mut self, events: EventSender) {
+        self.events = Some(events);
+    }
+
+    pub fn event_sender(&self) -> Option<&EventSender> {
+        self.events.as_ref()
+    }
+
+    pub fn pointer_motion(
+        &mut self,
+        windows: &WindowStack,
+        registry: &WindowRegistry,
+        x: ScreenSize,
+        y: ScreenSize,
+    ) {
+        if self.grab.is_none() {
+            let hit = Self::window_at(windows, x, y);
+            if hit != self.hovered {
+                if let Some(old) = self.hovered {
+                    self.send(registry, old, Event::MouseLeave);
+                }
+                if let Some(new) = hit {
+                    self.send(registry, new, Event::MouseEnter);
+                }
+                self.hovered = hit;
+            }
+        }
+        if let Some((window_id, x, y)) = self.target(windows, x, y) {
+            self.send(registry, window_id, Event::MousePosition { x, y });
+        }
+    }
+
+    pub fn pointer_button(
+        &mut self,
+        windows: &WindowStack,
+        registry: &WindowRegistry,
+        button: MouseButton,
+        pressed: bool,
+        x: ScreenSize,
+        y: ScreenSize,
+    ) {
+        if pressed {
+            if self.buttons_down == 0 {
+                self.grab = self.hovered;
+            }
+            self.buttons_down += 1;
+        }
+        if let Some((window_id, x, y)) = self.target(windows, x, y) {
+            let packet = if pressed {
+                Event::MouseDown { button, x, y }
+            } else {
+                Event::MouseUp { button, x, y }
+            };
+            self.send(registry, window_id, packet);
+        }
+        if !pressed {
+            self.buttons_down = self.buttons_down.saturating_sub(1);
+            if self.buttons_down == 0 {
+                self.grab = None;
+            }
+        }
+    }
+
+    pub fn key(
+        &mut self,
+        registry: &WindowRegistry,
+        key: KeyCode,
+        modifiers: Modifiers,
+        pressed: bool,
+    ) {
+        if let Some(window_id) = self.focused {
+            let packet = if pressed {
+                Event::KeyDown { key, modifiers }
+            } else {
+                Event::KeyUp { key, modifiers }
+            };
+            self.send(registry, window_id, packet);
+        }
+    }
+
+    /// Sends keyboard events to `window_id` from now on.
+    pub fn set_focused(&mut self, window_id: Option<u64>) {
+        self.focused = window_id;
+    }
+
+    /// Window that receives pointer events: the grab holder, else the one
+    /// under the pointer.
+    pub fn pointer_window(&self) -> Option<u64> {
+        self.grab.or(self.hovered)
+    }
+
+    /// Drops every reference to a window that is going away.
+    pub fn forget_window(&mut self, window_id: u64) {
+        for slot in [&mut self.hovered, &mut self.focused, &mut self.grab] {
+            if *slot == Some(window_id) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Window receiving pointer events and the pointer in its local coordinates.
+    fn target(
+        &self,
+        windows: &WindowStack,
+        x: ScreenSize,
+        y: ScreenSize,
+    ) -> Option<(u64, ScreenSize, ScreenSize)> {
+        let window_id = self.pointer_window()?;
+        let rect = windows.get(&window_id)?.get_content_rect();
+        Some((window_id, x - rect.position.x, y - rect.position.y))
+    }
+
+    /// Topmost window at `(x, y)`, if the point is in its client area rather
+    /// than on its decorations.
+    fn window_at(windows: &WindowStack, x: ScreenSize, y: ScreenSize) -> Option<u64> {
+        windows
+            .iter_top_down()
+            .find(|(_, window)| !window.is_minimized() && window.get_frame_rect().contains(x, y))
+            .filter(|(_, window)| window.get_content_rect().contains(x, y))
+            .map(|(id, _)| id)
+    }
+
+    /// Sends `event` to the client that owns `window_id`, naming the window
+    /// it is for.
+    pub fn send(&self, registry: &WindowRegistry, window_id: u64, event: Event) {
+        if let (Some(events), Some(connection)) = (&self.events, registry.owner(window_id)) {
+            events.send(connection, Packet::WindowEvent { window_id, event });
+        }
+    }
+}
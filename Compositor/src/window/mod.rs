@@ -1,2 +1,6 @@
+pub mod decoration;
 pub mod display_manager;
+pub mod input;
+pub mod registry;
+pub mod stack;
 pub mod window;
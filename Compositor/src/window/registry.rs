@@ -0,0 +1,82 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Closed windows remembered per connection for `used_to_own`.
+const RECENTLY_CLOSED_LIMIT: usize = 32;
+
+/// Hands out window IDs and records which connection owns each window.
+///
+/// IDs are allocated sequentially starting at 1 and never reused while the
+/// compositor runs, so a stale ID from a closed window cannot address a new one.
+pub struct WindowRegistry {
+    next_id: u64,
+    /// window id -> owning connection id
+    owners: HashMap<u64, u64>,
+    /// connection id -> windows it created, in creation order
+    windows: HashMap<u64, Vec<u64>>,
+    /// connection id -> windows it owned that were closed, oldest first
+    recently_closed: HashMap<u64, VecDeque<u64>>,
+}
+
+impl WindowRegistry {
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            owners: HashMap::new(),
+            windows: HashMap::new(),
+            recently_closed: HashMap::new(),
+        }
+    }
+
+    /// Allocates a new window ID owned by `connection_id`.
+    pub fn allocate(&mut self, connection_id: u64) -> u64 {
+        let window_id = self.next_id;
+        self.next_id += 1;
+        self.owners.insert(window_id, connection_id);
+        self.windows.entry(connection_id).or_default().push(window_id);
+        window_id
+    }
+
+    /// Forgets a window, returning the connection that owned it.
+    pub fn release(&mut self, window_id: u64) -> Option<u64> {
+        let connection_id = self.owners.remove(&window_id)?;
+        if let Some(windows) = self.windows.get_mut(&connection_id) {
+            windows.retain(|&id| id != window_id);
+            if windows.is_empty() {
+                self.windows.remove(&connection_id);
+            }
+        }
+        let closed = self.recently_closed.entry(connection_id).or_default();
+        if closed.len() == RECENTLY_CLOSED_LIMIT {
+            closed.pop_front();
+        }
+        closed.push_back(window_id);
+        Some(connection_id)
+    }
+
+    /// Forgets every window owned by a connection, returning their IDs.
+    pub fn release_connection(&mut self, connection_id: u64) -> Vec<u64> {
+        self.recently_closed.remove(&connection_id);
+        let windows = self.windows.remove(&connection_id).unwrap_or_default();
+        for window_id in &windows {
+            self.owners.remove(window_id);
+        }
+        windows
+    }
+
+    /// Checks if a connection owns any windows.
+    pub fn has_windows(&self, connection_id: u64) -> bool {
+        self.windows.contains_key(&connection_id)
+    }
+
+    pub fn owner(&self, window_id: u64) -> Option<u64> {
+        self.owners.get(&window_id).copied()
+    }
+
+    /// Checks if a connection owned a window that was closed recently, so
+    /// requests it sent before the close can be told apart from bad IDs.
+    pub fn used_to_own(&self, connection_id: u64, window_id: u64) -> bool {
+        self.recently_closed
+            .get(&connection_id)
+            .is_some_and(|closed| closed.contains(&window_id))
+    }
+}
@@ -0,0 +1,91 @@
+use crate::window::window::Window;
+use std::collections::HashMap;
+use std::ops::Index;
+
+/// Windows in stacking order.
+///
+/// Windows are looked up by ID like in a map, and also kept in a list from
+/// the bottom of the stack to the top: renderers draw them in that order and
+/// hit tests walk it backwards, so the window on top wins.
+pub struct WindowStack {
+    windows: HashMap<u64, Window>,
+    /// Window IDs from the bottom of the stack to the top
+    order: Vec<u64>,
+}
+
+impl WindowStack {
+    pub fn new() -> Self {
+        Self {
+            windows: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Adds a window on top of the stack.
+    pub fn insert(&mut self, window_id: u64, window: Window) {
+        if self.windows.insert(window_id, window).is_none() {
+            self.order.push(window_id);
+        }
+    }
+
+    pub fn remove(&mut self, window_id: &u64) -> Option<Window> {
+        let window = self.windows.remove(window_id)?;
+        self.order.retain(|id| id != window_id);
+        Some(window)
+    }
+
+    /// Moves a window to the top of the stack. Returns whether the order
+    /// changed.
+    pub fn raise(&mut self, window_id: u64) -> bool {
+        let Some(index) = self.order.iter().position(|&id| id == window_id) else {
+            return false;
+        };
+        if index + 1 == self.order.len() {
+            return false;
+        }
+        self.order.remove(index);
+        self.order.push(window_id);
+        true
+    }
+
+    pub fn get(&self, window_id: &u64) -> Option<&Window> {
+        self.windows.get(window_id)
+    }
+
+    pub fn get_mut(&mut self, window_id: &u64) -> Option<&mut Window> {
+        self.windows.get_mut(window_id)
+    }
+
+    /// Windows from the bottom of the stack to the top.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (u64, &Window)> {
+        self.order.iter().map(|id| (*id, &self.windows[id]))
+    }
+
+    /// Windows from the top of the stack to the bottom, for hit testing.
+    pub fn iter_top_down(&self) -> impl Iterator<Item = (u64, &Window)> {
+        self.iter().rev()
+    }
+
+    /// Every window, in no particular order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u64, &mut Window)> {
+        self.windows.iter_mut().map(|(id, window)| (*id, window))
+    }
+
+    /// Every window, in no particular order.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Window> {
+        self.windows.values_mut()
+    }
+
+    pub fn clear(&mut self) {
+        self.windows.clear();
+        self.order.clear();
+    }
+}
+
+impl Index<&u64> for WindowStack {
+    type Output = Window;
+
+    fn index(&self, window_id: &u64) -> &Window {
+        &self.windows[window_id]
+    }
+}
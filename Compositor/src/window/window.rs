@@ -29,13 +29,18 @@
 //! window.set_active(true);
 //! assert!(window.is_active());
 //!
-//! // Restore to original size/position
-//! window.restore();
+//! // Back to the size/position from before maximizing
+//! window.unmaximize();
+//! assert!(!window.is_maximized());
 //! ```
 
-use libprotocol::ScreenSize;
+use std::collections::VecDeque;
+use libprotocol::{MAX_SURFACE_SIZE, ScreenSize};
+use crate::common::cursor::ClientCursor;
 use crate::render::api::texture::Texture;
+use crate::render::util::image::Image;
 use crate::render::util::rect::{Position, Rect, Size};
+use crate::window::decoration::{ButtonState, TitleButton};
 
 /// Represents a window in the windowing system.
 ///
@@ -51,6 +56,7 @@ use crate::render::util::rect::{Position, Rect, Size};
 /// - Minimized state
 /// - Active (focused) state
 /// - Icon presence
+/// - Resizability
 ///
 /// # Memory Layout
 ///
@@ -67,26 +73,70 @@ pub struct Window {
     /// - Bit 2: Minimized state
     /// - Bit 3: Active (focused) state
     /// - Bit 4: Has icon
-    flags: u8,
+    /// - Bit 5: Frame changed since it was last drawn
+    /// - Bit 6: Fullscreen state
+    /// - Bit 7: Client acknowledges configures
+    /// - Bit 8: Resizable by the user
+    flags: u16,
 
-    /// Stores the window's geometry before maximize/minimize
+    /// Stores the window's geometry before maximize/fullscreen
     restore_rect: Option<Rect>,
 
-    /// Window icon texture
-    icon: Option<Texture>,
+    /// Latest frame painted by the client
+    frame: Option<Image>,
+    /// GPU copy of `frame`, sized to the window; owned by the render thread
+    content: Option<Texture>,
+
+    /// Window icon, scaled to the title bar's icon slot
+    icon: Option<Image>,
+    /// GPU copy of `icon`; owned by the render thread
+    icon_texture: Option<Texture>,
+    /// Identifies the application the window belongs to
+    app_id: Option<String>,
     /// Optional window title
     title: Option<String>,
+    /// Title bar button under the pointer and whether it is held down
+    title_button: Option<(TitleButton, ButtonState)>,
+    /// Cursor the client wants over its client area, if not the default
+    cursor: Option<ClientCursor>,
+    /// Smallest client area an interactive resize may leave
+    min_size: Size,
+    /// Largest client area an interactive resize may leave, if limited
+    max_size: Option<Size>,
+    /// Configures sent to the client and not acknowledged yet, oldest first
+    pending_configures: VecDeque<(u32, Size)>,
+    /// Serials of the first and last configure sent, so acks of configures
+    /// dropped from `pending_configures` are still recognised
+    sent_configures: Option<(u32, u32)>,
+    /// Size of the acknowledged configure the client has not painted at yet
+    acked_size: Option<Size>,
 }
 
 // Bit flag constants for window state
-const TITLE_BAR_FLAG: u8    = 0b0000_0001;
-const MAXIMIZED_FLAG: u8    = 0b0000_0010;
-const MINIMIZED_FLAG: u8    = 0b0000_0100;
-const ACTIVE_FLAG: u8       = 0b0000_1000;
-const HAS_ICON_FLAG: u8     = 0b0001_0000;
+const TITLE_BAR_FLAG: u16      = 0b0000_0000_0000_0001;
+const MAXIMIZED_FLAG: u16      = 0b0000_0000_0000_0010;
+const MINIMIZED_FLAG: u16      = 0b0000_0000_0000_0100;
+const ACTIVE_FLAG: u16         = 0b0000_0000_0000_1000;
+const HAS_ICON_FLAG: u16       = 0b0000_0000_0001_0000;
+const FRAME_DIRTY_FLAG: u16    = 0b0000_0000_0010_0000;
+const FULLSCREEN_FLAG: u16     = 0b0000_0000_0100_0000;
+const ACKS_CONFIGURE_FLAG: u16 = 0b0000_0000_1000_0000;
+const RESIZABLE_FLAG: u16      = 0b0000_0001_0000_0000;
+
+/// Unacknowledged configures kept per window; older ones are forgotten, so a
+/// client that never acknowledges cannot grow the list without bound.
+const MAX_PENDING_CONFIGURES: usize = 16;
 
 /// Padding used for window layout calculations
 pub const WINDOW_PADDING: ScreenSize = 3;
+/// Height of the title bar above the client area
+pub const TITLE_BAR_HEIGHT: ScreenSize = 30;
+/// Smallest client area a window can be resized to unless its client asks
+/// otherwise; wide enough for the title bar buttons and a few letters.
+pub const DEFAULT_MIN_SIZE: Size = Size {
+    width: 120,
+    height: 40,
+};
 
 impl Window {
     /// Creates a new window with a title bar and specified title.
@@ -108,10 +158,21 @@ impl Window {
     pub fn new_titled(title: String, rect: Rect) -> Self {
         Self {
             rect,
-            flags: TITLE_BAR_FLAG,  // Initialize with title bar visible
+            flags: TITLE_BAR_FLAG | RESIZABLE_FLAG,  // Initialize with title bar visible
             restore_rect: None,
+            frame: None,
+            content: None,
             icon: None,
+            icon_texture: None,
+            app_id: None,
             title: Some(title),
+            title_button: None,
+            cursor: None,
+            min_size: DEFAULT_MIN_SIZE,
+            max_size: None,
+            pending_configures: VecDeque::new(),
+            sent_configures: None,
+            acked_size: None,
         }
     }
 
@@ -130,10 +191,21 @@ impl Window {
     pub fn new_non_titled(rect: Rect) -> Self {
         Self {
             rect,
-            flags: 0,
+            flags: RESIZABLE_FLAG,
             restore_rect: None,
+            frame: None,
+            content: None,
             icon: None,
+            icon_texture: None,
+            app_id: None,
             title: None,
+            title_button: None,
+            cursor: None,
+            min_size: DEFAULT_MIN_SIZE,
+            max_size: None,
+            pending_configures: VecDeque::new(),
+            sent_configures: None,
+            acked_size: None,
         }
     }
 
@@ -173,11 +245,13 @@ impl Window {
 
     /// Maximizes the window, storing current geometry for later restoration.
     ///
+    /// The caller lays out the maximized geometry.
+    ///
     /// # State Changes
     ///
     /// - Sets maximized flag
     /// - Clears minimized flag
-    /// - Stores current geometry in restore_rect
+    /// - Stores current geometry in restore_rect unless fullscreen already did
     ///
     /// # Notes
     ///
@@ -185,50 +259,85 @@ impl Window {
     #[inline]
     pub fn maximize(&mut self) {
         if !self.is_maximized() {
-            self.restore_rect = Some(self.rect.clone());
+            self.save_restore_rect();
             self.flags |= MAXIMIZED_FLAG;
-            self.flags &= !MINIMIZED_FLAG;
         }
+        self.flags &= !MINIMIZED_FLAG;
     }
 
-    /// Minimizes the window, storing current geometry for later restoration.
+    /// Leaves the maximized state.
     ///
     /// # State Changes
     ///
-    /// - Sets minimized flag
-    /// - Clears maximized flag
-    /// - Stores current geometry in restore_rect if not already stored
+    /// - Clears maximized and minimized flags
+    /// - Restores the geometry from before maximizing, unless the window is
+    ///   still fullscreen
+    #[inline]
+    pub fn unmaximize(&mut self) {
+        self.flags &= !(MAXIMIZED_FLAG | MINIMIZED_FLAG);
+        if !self.is_fullscreen() {
+            self.take_restore_rect();
+        }
+    }
+
+    /// Covers the screen without decorations, storing current geometry for
+    /// later restoration.
     ///
-    /// # Notes
+    /// The caller lays out the fullscreen geometry. A maximized window stays
+    /// maximized underneath and goes back to that when leaving fullscreen.
+    ///
+    /// # State Changes
     ///
-    /// Multiple calls to minimize() while already minimized have no effect.
+    /// - Sets fullscreen flag
+    /// - Clears minimized flag
+    /// - Stores current geometry in restore_rect unless maximize already did
     #[inline]
-    pub fn minimize(&mut self) {
-        if !self.is_minimized() {
-            if self.restore_rect.is_none() {
-                self.restore_rect = Some(self.rect.clone());
-            }
-            self.flags |= MINIMIZED_FLAG;
-            self.flags &= !MAXIMIZED_FLAG;
+    pub fn fullscreen(&mut self) {
+        if !self.is_fullscreen() {
+            self.save_restore_rect();
+            self.flags |= FULLSCREEN_FLAG;
         }
+        self.flags &= !MINIMIZED_FLAG;
     }
 
-    /// Restores the window to its geometry before maximization or minimization.
+    /// Leaves the fullscreen state.
     ///
     /// # State Changes
     ///
-    /// - Clears both maximized and minimized flags
-    /// - Restores previous geometry if available
-    /// - Consumes the stored restore_rect
+    /// - Clears fullscreen and minimized flags
+    /// - Restores the geometry from before going fullscreen, unless the
+    ///   window is still maximized
+    #[inline]
+    pub fn unfullscreen(&mut self) {
+        self.flags &= !(FULLSCREEN_FLAG | MINIMIZED_FLAG);
+        if !self.is_maximized() {
+            self.take_restore_rect();
+        }
+    }
+
+    /// Hides the window, keeping its geometry and other state.
     ///
-    /// # Notes
+    /// # State Changes
     ///
-    /// Has no effect if there's no stored geometry to restore to.
+    /// - Sets minimized flag
     #[inline]
-    pub fn restore(&mut self) {
+    pub fn minimize(&mut self) {
+        self.flags |= MINIMIZED_FLAG;
+    }
+
+    /// Stores the current geometry unless the window already left its
+    /// normal state and stored it then.
+    #[inline]
+    fn save_restore_rect(&mut self) {
+        if !self.is_maximized() && !self.is_fullscreen() {
+            self.restore_rect = Some(self.rect);
+        }
+    }
+
+    #[inline]
+    fn take_restore_rect(&mut self) {
         if let Some(stored_rect) = self.restore_rect.take() {
             self.rect = stored_rect;
-            self.flags &= !(MAXIMIZED_FLAG | MINIMIZED_FLAG);
         }
     }
 
@@ -246,46 +355,49 @@ impl Window {
         }
     }
 
-    /// Sets the window's icon.
+    /// Sets or removes the window's icon.
     ///
     /// # Arguments
     ///
-    /// * `icon` - The texture to use as the window icon
+    /// * `icon` - The icon pixels, or None to remove the icon
     ///
-    /// # Examples
+    /// # Returns
     ///
-    /// ```rust
-    /// window.set_icon(some_texture);
-    /// assert!(window.has_icon());
-    /// ```
+    /// * `Option<Texture>` - The previous icon's texture, which the caller
+    ///   must `cleanup()` on the render thread
     #[inline]
-    pub fn set_icon(&mut self, icon: Texture) {
-        self.icon = Some(icon);
-        self.flags |= HAS_ICON_FLAG;
+    pub fn set_icon(&mut self, icon: Option<Image>) -> Option<Texture> {
+        if icon.is_some() {
+            self.flags |= HAS_ICON_FLAG;
+        } else {
+            self.flags &= !HAS_ICON_FLAG;
+        }
+        self.icon = icon;
+        self.icon_texture.take()
     }
 
-    /// Removes the window's icon if it exists.
-    ///
-    /// # Examples
-    ///
-    /// ```rust
-    /// window.remove_icon();
-    /// assert!(!window.has_icon());
-    /// ```
+    /// Returns the window's icon pixels if it has an icon.
+    #[inline]
+    pub fn get_icon(&self) -> Option<&Image> {
+        self.icon.as_ref()
+    }
+
+    /// Returns the texture the icon is uploaded to, if it has been yet.
     #[inline]
-    pub fn remove_icon(&mut self) {
-        self.icon = None;
-        self.flags &= !HAS_ICON_FLAG;
+    pub fn get_icon_texture(&self) -> Option<&Texture> {
+        self.icon_texture.as_ref()
     }
 
-    /// Returns a reference to the window's icon if it exists.
-    ///
-    /// # Returns
-    ///
-    /// * `Option<&Texture>` - Reference to the window's icon texture if present
+    /// Installs the texture the icon was uploaded to.
     #[inline]
-    pub fn get_icon(&self) -> Option<&Texture> {
-        self.icon.as_ref()
+    pub fn set_icon_texture(&mut self, texture: Texture) {
+        self.icon_texture = Some(texture);
+    }
+
+    /// Removes the icon texture so it can be freed on the render thread.
+    #[inline]
+    pub fn take_icon_texture(&mut self) -> Option<Texture> {
+        self.icon_texture.take()
     }
 
     /// Checks if the window has an icon.
@@ -302,6 +414,177 @@ impl Window {
         (self.flags & HAS_ICON_FLAG) != 0
     }
 
+    /// Replaces the window contents with a newly painted frame, which may
+    /// differ in size from the window.
+    #[inline]
+    pub fn set_frame(&mut self, frame: Image) {
+        let size = Size {
+            width: frame.width(),
+            height: frame.height(),
+        };
+        if self.acked_size == Some(size) {
+            self.acked_size = None;
+        }
+        self.frame = Some(frame);
+        self.flags |= FRAME_DIRTY_FLAG;
+    }
+
+    /// Sets whether the client acknowledges configures before painting at
+    /// a new size. Only then are frames held back during resizes.
+    #[inline]
+    pub fn set_acks_configure(&mut self, acks: bool) {
+        if acks {
+            self.flags |= ACKS_CONFIGURE_FLAG;
+        } else {
+            self.flags &= !ACKS_CONFIGURE_FLAG;
+        }
+    }
+
+    /// Records a configure sent with `serial` announcing `size`, if the
+    /// client acknowledges configures.
+    #[inline]
+    pub fn push_configure(&mut self, serial: u32, size: Size) {
+        if (self.flags & ACKS_CONFIGURE_FLAG) == 0 {
+            return;
+        }
+        if self.pending_configures.len() == MAX_PENDING_CONFIGURES {
+            self.pending_configures.pop_front();
+        }
+        self.pending_configures.push_back((serial, size));
+        let first = self.sent_configures.map_or(serial, |(first, _)| first);
+        self.sent_configures = Some((first, serial));
+    }
+
+    /// Marks a configure and every earlier one as acknowledged. Returns
+    /// false if the serial is outside those sent to this window.
+    ///
+    /// Acks of configures that were already acknowledged or dropped to make
+    /// room for newer ones are accepted and change nothing.
+    #[inline]
+    pub fn ack_configure(&mut self, serial: u32) -> bool {
+        let pending = self.pending_configures.iter().position(|&(pending, _)| pending == serial);
+        let Some(index) = pending else {
+            // Serials wrap, so the range is measured from the first one
+            return self
+                .sent_configures
+                .is_some_and(|(first, last)| serial.wrapping_sub(first) <= last.wrapping_sub(first));
+        };
+        self.acked_size = self.pending_configures.drain(..=index).next_back().map(|(_, size)| size);
+        true
+    }
+
+    /// Checks if a frame of the given size should replace the current one.
+    ///
+    /// While a configure is pending, the last frame stays on screen until
+    /// the client paints at the size it acknowledged, so a resize never
+    /// shows a frame drawn for some other size.
+    #[inline]
+    pub fn accepts_frame(&self, width: ScreenSize, height: ScreenSize) -> bool {
+        match self.acked_size {
+            Some(size) => size == Size { width, height },
+            None => self.pending_configures.is_empty(),
+        }
+    }
+
+    /// Returns the latest frame painted by the client, if any.
+    #[inline]
+    pub fn get_frame(&self) -> Option<&Image> {
+        self.frame.as_ref()
+    }
+
+    /// Checks if a new frame arrived since the last call to `clear_frame_dirty()`.
+    #[inline]
+    pub fn is_frame_dirty(&self) -> bool {
+        (self.flags & FRAME_DIRTY_FLAG) != 0
+    }
+
+    /// Marks the current frame as drawn.
+    #[inline]
+    pub fn clear_frame_dirty(&mut self) {
+        self.flags &= !FRAME_DIRTY_FLAG;
+    }
+
+    /// Returns the texture holding the window contents, if one was uploaded.
+    #[inline]
+    pub fn get_content(&self) -> Option<&Texture> {
+        self.content.as_ref()
+    }
+
+    /// Returns the latest frame together with the texture it is uploaded to.
+    #[inline]
+    pub fn get_frame_and_content(&mut self) -> (Option<&Image>, Option<&mut Texture>) {
+        (self.frame.as_ref(), self.content.as_mut())
+    }
+
+    /// Installs a new content texture, handing back the previous one.
+    ///
+    /// # Notes
+    ///
+    /// Textures have no `Drop`; the caller must `cleanup()` the returned
+    /// texture on the render thread.
+    #[inline]
+    pub fn set_content(&mut self, content: Texture) -> Option<Texture> {
+        self.content.replace(content)
+    }
+
+    /// Removes the content texture so it can be freed on the render thread.
+    #[inline]
+    pub fn take_content(&mut self) -> Option<Texture> {
+        self.content.take()
+    }
+
+    /// Sets which title bar button is highlighted. Returns true if that
+    /// changed and the window needs redrawing.
+    #[inline]
+    pub fn set_title_button(&mut self, title_button: Option<(TitleButton, ButtonState)>) -> bool {
+        let changed = self.title_button != title_button;
+        self.title_button = title_button;
+        changed
+    }
+
+    /// Returns the state of a title bar button, or None if it is not highlighted.
+    #[inline]
+    pub fn get_title_button_state(&self, button: TitleButton) -> Option<ButtonState> {
+        self.title_button
+            .filter(|&(highlighted, _)| highlighted == button)
+            .map(|(_, state)| state)
+    }
+
+    /// Sets the cursor shown while the pointer is over the client area.
+    #[inline]
+    pub fn set_cursor(&mut self, cursor: ClientCursor) {
+        self.cursor = Some(cursor);
+    }
+
+    /// Returns the cursor the client set, or None for the default.
+    #[inline]
+    pub fn get_cursor(&self) -> Option<&ClientCursor> {
+        self.cursor.as_ref()
+    }
+
+    /// Sets the size range interactive resizing keeps the client area in.
+    /// `None` leaves the size unlimited.
+    #[inline]
+    pub fn set_size_limits(&mut self, min: Size, max: Option<Size>) {
+        self.min_size = min;
+        self.max_size = max;
+    }
+
+    /// Returns the smallest size interactive resizing may leave.
+    #[inline]
+    pub fn get_min_size(&self) -> Size {
+        self.min_size
+    }
+
+    /// Returns the largest size interactive resizing may leave.
+    #[inline]
+    pub fn get_max_size(&self) -> Size {
+        self.max_size.unwrap_or(Size {
+            width: MAX_SURFACE_SIZE,
+            height: MAX_SURFACE_SIZE,
+        })
+    }
+
     /// Toggles the visibility of the window's title bar.
     ///
     /// Uses efficient bitwise XOR operation for toggle.
@@ -310,6 +593,38 @@ impl Window {
         self.flags ^= TITLE_BAR_FLAG;
     }
 
+    /// Shows or hides the window's title bar and frame.
+    #[inline]
+    pub fn set_title_bar(&mut self, visible: bool) {
+        if visible {
+            self.flags |= TITLE_BAR_FLAG;
+        } else {
+            self.flags &= !TITLE_BAR_FLAG;
+        }
+    }
+
+    /// Sets whether the user can resize the window by dragging its edges.
+    #[inline]
+    pub fn set_resizable(&mut self, resizable: bool) {
+        if resizable {
+            self.flags |= RESIZABLE_FLAG;
+        } else {
+            self.flags &= !RESIZABLE_FLAG;
+        }
+    }
+
+    /// Checks if the user can resize the window by dragging its edges.
+    #[inline]
+    pub fn is_resizable(&self) -> bool {
+        (self.flags & RESIZABLE_FLAG) != 0
+    }
+
+    /// Sets or clears the ID of the application the window belongs to.
+    #[inline]
+    pub fn set_app_id(&mut self, app_id: Option<String>) {
+        self.app_id = app_id;
+    }
+
     /// Updates the window's title text.
     ///
     /// # Arguments
@@ -350,6 +665,19 @@ impl Window {
         (self.flags & MINIMIZED_FLAG) != 0
     }
 
+    /// Checks if the window is currently fullscreen.
+    #[inline]
+    pub fn is_fullscreen(&self) -> bool {
+        (self.flags & FULLSCREEN_FLAG) != 0
+    }
+
+    /// Checks if the title bar and frame are drawn around the client area,
+    /// which they never are while fullscreen.
+    #[inline]
+    pub fn has_decorations(&self) -> bool {
+        self.has_title_bar() && !self.is_fullscreen()
+    }
+
     /// Checks if the window is currently active (focused).
     ///
     /// # Returns
@@ -380,14 +708,14 @@ impl Window {
         self.rect.position
     }
 
-    /// Returns a reference to the window's title.
+    /// Returns the text for the window's title bar.
     ///
     /// # Returns
     ///
-    /// * `&Option<String>` - Reference to the optional title text
+    /// * `Option<&str>` - The title, or the app ID while there is no title
     #[inline]
-    pub fn get_title(&self) -> &Option<String> {
-        &self.title
+    pub fn get_title(&self) -> Option<&str> {
+        self.title.as_deref().or(self.app_id.as_deref())
     }
 
     /// Returns a mutable reference to the window's render rectangle.
@@ -411,4 +739,96 @@ impl Window {
     pub fn get_render_rect(&self) -> &Rect {
         &self.rect
     }
-}
\ No newline at end of file
+
+    /// Returns the screen area showing the client's contents: the window
+    /// rect below the title bar, inset by the padding.
+    #[inline]
+    pub fn get_content_rect(&self) -> Rect {
+        if self.is_fullscreen() {
+            return self.rect;
+        }
+        Rect::new(
+            self.rect.position.x + WINDOW_PADDING,
+            self.rect.position.y + TITLE_BAR_HEIGHT,
+            self.rect.size.width,
+            self.rect.size.height,
+        )
+    }
+
+    /// Returns the screen area the window covers, title bar and padding
+    /// included.
+    #[inline]
+    pub fn get_frame_rect(&self) -> Rect {
+        if !self.has_decorations() {
+            return self.get_content_rect();
+        }
+        Rect::new(
+            self.rect.position.x,
+            self.rect.position.y,
+            self.rect.size.width + WINDOW_PADDING * 2,
+            self.rect.size.height + TITLE_BAR_HEIGHT + WINDOW_PADDING,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acking_window() -> Window {
+        let mut window = Window::new_non_titled(Rect::new(0, 0, 100, 100));
+        window.set_acks_configure(true);
+        window
+    }
+
+    #[test]
+    fn ack_of_evicted_configure_is_accepted() {
+        let mut window = acking_window();
+        let count = MAX_PENDING_CONFIGURES as u32 + 4;
+        for serial in 1..=count {
+            window.push_configure(serial, Size { width: serial as ScreenSize, height: 100 });
+        }
+        // Serial 1 was dropped to make room, but the client did get it
+        assert!(window.ack_configure(1));
+        assert!(!window.accepts_frame(1, 100));
+        assert!(window.ack_configure(count));
+        assert!(window.accepts_frame(count as ScreenSize, 100));
+    }
+
+    #[test]
+    fn ack_of_unsent_configure_is_rejected() {
+        let mut window = acking_window();
+        assert!(!window.ack_configure(1));
+        window.push_configure(1, Size { width: 50, height: 50 });
+        assert!(!window.ack_configure(2));
+        assert!(window.ack_configure(1));
+        // Acking it again changes nothing
+        assert!(window.ack_configure(1));
+        assert!(window.accepts_frame(50, 50));
+    }
+
+    #[test]
+    fn ack_outside_sent_serials_is_rejected() {
+        let mut window = acking_window();
+        // The serial counter is shared, so other windows got the gaps
+        window.push_configure(10, Size { width: 50, height: 50 });
+        window.push_configure(20, Size { width: 60, height: 60 });
+        assert!(window.ack_configure(20));
+        assert!(window.ack_configure(15));
+        assert!(!window.ack_configure(9));
+        assert!(!window.ack_configure(21));
+        assert!(!window.ack_configure(u32::MAX));
+    }
+
+    #[test]
+    fn sent_serials_may_wrap() {
+        let mut window = acking_window();
+        window.push_configure(u32::MAX - 1, Size { width: 50, height: 50 });
+        window.push_configure(1, Size { width: 60, height: 60 });
+        assert!(window.ack_configure(1));
+        assert!(window.ack_configure(u32::MAX));
+        assert!(window.ack_configure(0));
+        assert!(!window.ack_configure(2));
+        assert!(!window.ack_configure(u32::MAX - 2));
+    }
+}
@@ -1,12 +1,29 @@
-use libcrystalmatrix::{open_window};
+use libcrystalmatrix::Connection;
 use std::sync::{Arc, Mutex};
 use libcrystalmatrix::libprotocol::Packet;
+use libcrystalmatrix::libprotocol::shm::BYTES_PER_PIXEL;
+
+fn gradient(width: i32, height: i32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * BYTES_PER_PIXEL);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.extend_from_slice(&[
+                (x * 255 / width) as u8,
+                (y * 255 / height) as u8,
+                160,
+                255,
+            ]);
+        }
+    }
+    pixels
+}
 
 fn main() {
     let should_close = Arc::new(Mutex::new(false));
     let should_close_clone = Arc::clone(&should_close);
 
-    let client = open_window(Some("Flourite".to_string()),1280/2 ,720/2,move |packet| {
+    let connection = Connection::open().expect("Failed to connect to the compositor");
+    let window = connection.create_window(Some("Flourite".to_string()),1280/2 ,720/2,move |packet| {
         match packet {
             Packet::Closed => {
                 let mut should_close = should_close_clone.lock().unwrap();
@@ -17,13 +34,19 @@ fn main() {
         None
     }).expect("Failed to open window");
 
+    let (width, height) = window.get_size().expect("Window closed before painting");
+    window
+        .paint(&gradient(width, height), width, height)
+        .expect("Failed to paint window");
+
     loop {
         if *should_close.lock().unwrap() {
             break;
         }
-        client.pump_window();
+        if connection.dispatch().is_err() {
+            break;
+        }
     }
 
-    client.close_window();
+    window.close();
 }
-
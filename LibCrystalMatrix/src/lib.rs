@@ -1,27 +1,71 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
 use std::io;
-use std::io::{ErrorKind, Read, Write};
+use std::io::ErrorKind;
 use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 pub use libprotocol;
-use libprotocol::{Packet, ScreenSize, PROTOCOL_VERSION, WINDOW_UNIX_SOCKET_NAME};
-struct ClientState {
+use libprotocol::shm::{ShmBuffer, BYTES_PER_PIXEL};
+use libprotocol::{
+    BackgroundFill, Capabilities, CursorShape, Event, Negotiated, Packet, PacketCodec, ScreenSize,
+    SharedFd, WallpaperMode, WindowState, MAX_CURSOR_SIZE, MAX_ICON_SIZE, PROTOCOL_CAPABILITIES,
+    PROTOCOL_VERSION,
+};
+
+type Callback = Arc<dyn Fn(Packet) -> Option<Packet> + Send + Sync>;
+
+/// Where a packet from the compositor goes once the connection is unlocked,
+/// so callbacks are free to use the connection and its windows.
+enum Routed {
+    /// To the callback of the window it is for
+    Window(Callback, Packet),
+    /// To whoever called `Connection::dispatch`
+    Connection(Packet),
+    /// Nowhere else; it only updated the window's state
+    Handled,
+}
+
+struct ConnectionState {
     stream: UnixStream,
-    callback: Box<dyn Fn(Packet) -> Option<Packet> + Send + Sync>,
+    codec: PacketCodec,
+    // Agreed with the compositor in the `Hello` exchange
+    negotiated: Negotiated,
+    // Open windows by ID
+    windows: HashMap<u64, WindowData>,
+    // Packets for the connection that arrived while creating a window
+    pending: VecDeque<Packet>,
+}
+
+struct WindowData {
+    callback: Callback,
+    // Set once the compositor sent `Closed`
     already_closed: bool,
     size: (ScreenSize, ScreenSize),
+    window_state: WindowState,
     position: (ScreenSize, ScreenSize),
-    window_id: u64,
+    // Reused between paints, grown when a larger frame comes along
+    buffer: Option<ShmBuffer>,
+}
+
+/// A connection to the compositor, which can own any number of windows.
+///
+/// Events only arrive while `dispatch` is called; each goes to the callback
+/// of the window it is for, and `dispatch` returns the rest.
+pub struct Connection {
+    state: Arc<Mutex<ConnectionState>>,
 }
 
-// Each instance gets its own isolated state
-pub struct Client {
-    state: Arc<Mutex<Option<ClientState>>>,
+/// A window opened on a `Connection`. Dropping it closes the window.
+pub struct Window {
+    state: Arc<Mutex<ConnectionState>>,
+    window_id: u64,
 }
 
 // Thread-safe singleton pattern to track all active clients
 struct ClientRegistry {
-    clients: Mutex<Vec<Arc<Mutex<Option<ClientState>>>>>,
+    clients: Mutex<Vec<Arc<Mutex<ConnectionState>>>>,
 }
 
 impl ClientRegistry {
@@ -40,169 +84,602 @@ impl ClientRegistry {
         }
     }
 
-    fn register_client(&self, state: Arc<Mutex<Option<ClientState>>>) {
+    fn register_client(&self, state: Arc<Mutex<ConnectionState>>) {
         let mut clients = self.clients.lock().unwrap();
         clients.push(state);
     }
 
-    fn unregister_client(&self, state: &Arc<Mutex<Option<ClientState>>>) {
+    fn unregister_client(&self, state: &Arc<Mutex<ConnectionState>>) {
         let mut clients = self.clients.lock().unwrap();
         clients.retain(|x| !Arc::ptr_eq(x, state));
     }
 }
 
-pub fn open_window(
-    title: Option<String>,
-    width: ScreenSize,
-    height: ScreenSize,
-    callback: impl Fn(Packet) -> Option<Packet> + Send + Sync + 'static,
-) -> std::io::Result<Arc<Client>> {
-    let client = Client {
-        state: Arc::new(Mutex::new(None)),
-    };
+impl Connection {
+    /// Connects to the compositor serving `$PRISM_DISPLAY` and agrees on a
+    /// protocol version and features with it.
+    pub fn open() -> io::Result<Connection> {
+        Self::open_at(&libprotocol::socket_path()?)
+    }
 
-    // Connect to the
-    let stream = UnixStream::connect(&WINDOW_UNIX_SOCKET_NAME)?;
-    let create_packet = Packet::Create {
-        width,
-        height,
-        title,
-    };
+    /// Connects to the compositor's control socket instead, which also
+    /// offers the features of desktop settings tools, such as changing the
+    /// wallpaper.
+    pub fn open_control() -> io::Result<Connection> {
+        Self::open_at(&libprotocol::control_socket_path(&libprotocol::socket_path()?))
+    }
 
-    let mut state = ClientState {
-        stream,
-        callback: Box::new(callback),
-        already_closed: false,
-        size: (width, height),
-        position: (-1, -1),
-        window_id: 0,
-    };
+    fn open_at(path: &Path) -> io::Result<Connection> {
+        let stream = UnixStream::connect(path)?;
+        let mut state = ConnectionState {
+            stream,
+            codec: PacketCodec::new(),
+            negotiated: Negotiated {
+                minor: 0,
+                capabilities: Capabilities::empty(),
+            },
+            windows: HashMap::new(),
+            pending: VecDeque::new(),
+        };
 
-    send_packet(&mut state.stream, &create_packet)?;
-    std::thread::sleep(std::time::Duration::from_millis(5));
-    {
-        let packet = receive_packet(&mut state.stream)?;
-        match packet {
-            Packet::CreateSuccess { window_id } => {
-                state.window_id = window_id;
+        let (major, minor, patch) = PROTOCOL_VERSION;
+        let hello = Packet::Hello {
+            major,
+            minor,
+            patch,
+            capabilities: PROTOCOL_CAPABILITIES,
+        };
+        send_packet(&mut state, &hello)?;
+        match receive_packet(&mut state)? {
+            Packet::Hello {
+                major,
+                minor,
+                capabilities,
+                ..
+            } => {
+                let negotiated = libprotocol::negotiate(major, minor, capabilities);
+                state.negotiated = negotiated.ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::ConnectionAborted,
+                        format!(
+                            "compositor speaks protocol {}.{}, this library {}.{}",
+                            major, minor, PROTOCOL_VERSION.0, PROTOCOL_VERSION.1
+                        ),
+                    )
+                })?;
+            }
+            Packet::Error { code, message, .. } => {
+                return Err(io::Error::new(
+                    ErrorKind::ConnectionAborted,
+                    format!("compositor refused the connection: {}: {}", code, message),
+                ));
             }
             _ => {
-                return Err(std::io::Error::new(
+                return Err(io::Error::new(
                     ErrorKind::ConnectionAborted,
                     "compositor sent the wrong packet type",
                 ));
             }
         }
+
+        // Register the client
+        let connection = Connection {
+            state: Arc::new(Mutex::new(state)),
+        };
+        ClientRegistry::get_instance().register_client(connection.state.clone());
+        Ok(connection)
     }
-    send_packet(&mut state.stream, &Packet::RequestAPIVersion)?;
-    std::thread::sleep(std::time::Duration::from_millis(5));
-    {
-        let packet = receive_packet(&mut state.stream)?;
-        match packet {
-            Packet::APIVersion {
-                major,
-                minor,
-                patch,
-            } => {
-                let (expected_major, expected_minor, expected_patch) = PROTOCOL_VERSION;
-                if major != expected_major || minor != expected_minor || patch != expected_patch {
-                    send_packet(&mut state.stream, &Packet::Close { window_id: 0 }).expect("");
-                    return Err(std::io::Error::new(
-                        ErrorKind::ConnectionAborted,
-                        "ABI version is not the same as the windowing lib refused to connect",
+
+    /// Opens a window. `callback` gets the window's events and may answer
+    /// each with a packet to send back.
+    ///
+    /// Compositors without `Capabilities::MULTI_WINDOW` allow one window per
+    /// connection.
+    pub fn create_window(
+        &self,
+        title: Option<String>,
+        width: ScreenSize,
+        height: ScreenSize,
+        callback: impl Fn(Packet) -> Option<Packet> + Send + Sync + 'static,
+    ) -> io::Result<Window> {
+        // Events for windows that are already open, delivered at the end
+        let mut routed = Vec::new();
+        let result = self.create_window_locked(title, width, height, Arc::new(callback), &mut routed);
+        for routed in routed {
+            if let Some(packet) = deliver(&self.state, routed)? {
+                self.state.lock().unwrap().pending.push_back(packet);
+            }
+        }
+        result
+    }
+
+    fn create_window_locked(
+        &self,
+        title: Option<String>,
+        width: ScreenSize,
+        height: ScreenSize,
+        callback: Callback,
+        routed: &mut Vec<Routed>,
+    ) -> io::Result<Window> {
+        let mut state = self.state.lock().unwrap();
+        if !state.windows.is_empty() {
+            check_supported(&state, Capabilities::MULTI_WINDOW, "multiple windows")?;
+        }
+        let create = Packet::Create {
+            width,
+            height,
+            title,
+        };
+        send_packet(&mut state, &create)?;
+        loop {
+            match receive_packet(&mut state)? {
+                Packet::CreateSuccess { window_id } => {
+                    let window = WindowData {
+                        callback,
+                        already_closed: false,
+                        size: (width, height),
+                        window_state: WindowState::default(),
+                        position: (-1, -1),
+                        buffer: None,
+                    };
+                    state.windows.insert(window_id, window);
+                    return Ok(Window {
+                        state: self.state.clone(),
+                        window_id,
+                    });
+                }
+                Packet::Error {
+                    request,
+                    code,
+                    message,
+                } if request == "Create" => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("compositor refused to create the window: {}: {}", code, message),
                     ));
                 }
-            }
-            _ => {
-                return Err(std::io::Error::new(
-                    ErrorKind::ConnectionAborted,
-                    "compositor sent the wrong packet type",
-                ));
+                packet => routed.push(route_packet(&mut state, packet)?),
             }
         }
     }
 
-    // Register the client
-    let registry = ClientRegistry::get_instance();
-    *client.state.lock().unwrap() = Some(state);
-    registry.register_client(client.state.clone());
+    /// Waits for the next packet from the compositor and hands it to the
+    /// window it is for.
+    ///
+    /// Returns packets that are not for a window, such as errors.
+    pub fn dispatch(&self) -> io::Result<Option<Packet>> {
+        let routed = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(packet) = state.pending.pop_front() {
+                return Ok(Some(packet));
+            }
+            let packet = receive_packet(&mut state)?;
+            route_packet(&mut state, packet)?
+        };
+        deliver(&self.state, routed)
+    }
+
+    /// Changes the desktop wallpaper to the PNG or JPEG image at `path`,
+    /// laid out over `fill`. Without a path the background is just `fill`.
+    ///
+    /// The file is opened here and handed to the compositor, which answers
+    /// with an `InvalidImage` error, returned by `dispatch`, if it cannot
+    /// decode it. Only
+    /// connections made with `open_control` may change the wallpaper.
+    pub fn set_wallpaper(
+        &self,
+        path: Option<&Path>,
+        mode: WallpaperMode,
+        fill: BackgroundFill,
+    ) -> io::Result<()> {
+        let image = match path {
+            Some(path) => SharedFd::new(File::open(path)?.into()),
+            None => SharedFd::default(),
+        };
+        let mut state = self.state.lock().unwrap();
+        check_supported(&state, Capabilities::WALLPAPER, "wallpapers")?;
+        let packet = Packet::SetWallpaper { mode, fill, image };
+        send_packet(&mut state, &packet)
+    }
 
-    Ok(Arc::new(client))
+    /// Features both this library and the compositor support.
+    pub fn capabilities(&self) -> Capabilities {
+        self.state.lock().unwrap().negotiated.capabilities
+    }
+
+    pub fn supports(&self, capabilities: Capabilities) -> bool {
+        self.capabilities().contains(capabilities)
+    }
 }
 
-impl Client {
-    pub fn pump_window(&self) {
-        if let Some(state) = self.state.lock().unwrap().as_mut() {
-            if let Ok(packet) = receive_packet(&mut state.stream) {
-                match packet {
-                    Packet::Closed => {
-                        state.already_closed = true;
-                    }
-                    Packet::Resize { width, height } => {
-                        state.size = (width, height);
-                        return;
-                    }
-                    Packet::Position { x, y } => {
-                        state.position = (x, y);
-                        return;
-                    }
-                    _ => {}
-                }
-                if let Some(response) = (state.callback)(packet) {
-                    let _ = send_packet(&mut state.stream, &response);
-                }
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Windows still open keep the socket alive
+        ClientRegistry::get_instance().unregister_client(&self.state);
+    }
+}
+
+impl Window {
+    /// ID the compositor gave the window.
+    pub fn id(&self) -> u64 {
+        self.window_id
+    }
+
+    /// Sends a new frame for the window.
+    ///
+    /// `pixels` holds `width * height` tightly packed RGBA pixels. They are
+    /// copied into a shared-memory buffer that the compositor maps directly.
+    /// Once the compositor resizes the window, it keeps showing the previous
+    /// frame until one arrives at the new `get_size()`.
+    pub fn paint(&self, pixels: &[u8], width: ScreenSize, height: ScreenSize) -> io::Result<()> {
+        if width <= 0 || height <= 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "frame size must be positive"));
+        }
+        let len = width as usize * height as usize * BYTES_PER_PIXEL;
+        if pixels.len() != len {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "pixel data does not match the frame size",
+            ));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let window = open_window(&mut state, self.window_id)?;
+        let buffer = match window.buffer.as_mut() {
+            Some(buffer) => {
+                buffer.ensure_len(len)?;
+                buffer
+            }
+            None => window.buffer.insert(ShmBuffer::create(len)?),
+        };
+        buffer.as_mut_slice()[..len].copy_from_slice(pixels);
+
+        let paint = Packet::Paint {
+            window_id: self.window_id,
+            width,
+            height,
+            buffer: SharedFd::new(buffer.share()?),
+        };
+        send_packet(&mut state, &paint)
+    }
+
+    /// Shows a cursor from the compositor's theme while the pointer is over
+    /// the window. `CursorShape::Default` goes back to the normal cursor.
+    pub fn set_cursor(&self, shape: CursorShape) -> io::Result<()> {
+        self.send_window_request(Capabilities::CURSORS, "client cursors", |window_id| {
+            Packet::SetCursor { window_id, shape }
+        })
+    }
+
+    /// Shows a cursor image while the pointer is over the window.
+    ///
+    /// `pixels` holds `width * height` tightly packed RGBA pixels, at most
+    /// `MAX_CURSOR_SIZE` on either side. The hotspot is the pixel that sits
+    /// under the pointer and has to lie inside the image.
+    pub fn set_custom_cursor(
+        &self,
+        pixels: &[u8],
+        width: ScreenSize,
+        height: ScreenSize,
+        hotspot_x: ScreenSize,
+        hotspot_y: ScreenSize,
+    ) -> io::Result<()> {
+        if !(1..=MAX_CURSOR_SIZE).contains(&width) || !(1..=MAX_CURSOR_SIZE).contains(&height) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "cursor size out of range"));
+        }
+        if !(0..width).contains(&hotspot_x) || !(0..height).contains(&hotspot_y) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "hotspot lies outside the cursor"));
+        }
+        let len = width as usize * height as usize * BYTES_PER_PIXEL;
+        if pixels.len() != len {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "pixel data does not match the cursor size",
+            ));
+        }
+
+        // A buffer of its own, since the paint buffer is rewritten every frame
+        let mut buffer = ShmBuffer::create(len)?;
+        buffer.as_mut_slice()[..len].copy_from_slice(pixels);
+        let buffer = SharedFd::new(buffer.share()?);
+        self.send_window_request(Capabilities::CURSORS, "client cursors", |window_id| {
+            Packet::SetCustomCursor {
+                window_id,
+                width,
+                height,
+                hotspot_x,
+                hotspot_y,
+                buffer,
             }
+        })
+    }
+
+    /// Limits how far the user can resize the window. `max` of `None`
+    /// leaves the size unlimited. A window outside the new range is resized
+    /// into it, which `get_size` picks up.
+    pub fn set_size_limits(
+        &self,
+        min: (ScreenSize, ScreenSize),
+        max: Option<(ScreenSize, ScreenSize)>,
+    ) -> io::Result<()> {
+        if min.0 <= 0 || min.1 <= 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "minimum size must be positive"));
+        }
+        if max.is_some_and(|max| max.0 < min.0 || max.1 < min.1) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "maximum size is below the minimum",
+            ));
         }
+        self.send_window_request(Capabilities::SIZE_LIMITS, "size limits", |window_id| {
+            Packet::SetSizeLimits {
+                window_id,
+                min_width: min.0,
+                min_height: min.1,
+                max_width: max.map(|max| max.0),
+                max_height: max.map(|max| max.1),
+            }
+        })
+    }
+
+    /// Fills the screen with the window, keeping its decorations.
+    pub fn maximize(&self) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_STATES, "window states", |window_id| {
+            Packet::Maximize { window_id }
+        })
+    }
+
+    /// Puts a maximized window back where it was.
+    pub fn unmaximize(&self) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_STATES, "window states", |window_id| {
+            Packet::Unmaximize { window_id }
+        })
     }
 
-    pub fn get_window_size(&self) -> (ScreenSize, ScreenSize) {
-        self.state.lock().unwrap().as_ref().unwrap().size.clone()
+    /// Hides the window.
+    pub fn minimize(&self) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_STATES, "window states", |window_id| {
+            Packet::Minimize { window_id }
+        })
     }
 
-    pub fn get_window_position(&self) -> (ScreenSize, ScreenSize) {
-        self.state
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .position
-            .clone()
+    /// Covers the whole screen with the window, without decorations.
+    pub fn fullscreen(&self) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_STATES, "window states", |window_id| {
+            Packet::Fullscreen { window_id }
+        })
     }
 
-    pub fn close_window(&self) {
-        let registry = ClientRegistry::get_instance();
+    /// Puts a fullscreen window back where it was.
+    pub fn unfullscreen(&self) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_STATES, "window states", |window_id| {
+            Packet::Unfullscreen { window_id }
+        })
+    }
 
-        if let Some(state) = self.state.lock().unwrap().as_mut() {
-            // Only send close packet if we haven't received Closed from
-            if !state.already_closed {
-                let close_packet = Packet::Close {
-                    window_id: state.window_id,
-                };
-                let _ = send_packet(&mut state.stream, &close_packet);
+    /// Changes the text in the title bar; `None` leaves it empty.
+    pub fn set_title(&self, title: Option<&str>) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_PROPERTIES, "window properties", |window_id| {
+            Packet::SetTitle {
+                window_id,
+                title: title.map(str::to_owned),
             }
+        })
+    }
+
+    /// Names the application the window belongs to, such as
+    /// `org.example.Editor`.
+    pub fn set_app_id(&self, app_id: Option<&str>) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_PROPERTIES, "window properties", |window_id| {
+            Packet::SetAppId {
+                window_id,
+                app_id: app_id.map(str::to_owned),
+            }
+        })
+    }
+
+    /// Shows an icon in the title bar, scaled to fit by the compositor.
+    ///
+    /// `pixels` holds `width * height` tightly packed RGBA pixels, at most
+    /// `MAX_ICON_SIZE` on either side.
+    pub fn set_icon(&self, pixels: &[u8], width: ScreenSize, height: ScreenSize) -> io::Result<()> {
+        if !(1..=MAX_ICON_SIZE).contains(&width) || !(1..=MAX_ICON_SIZE).contains(&height) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "icon size out of range"));
+        }
+        let len = width as usize * height as usize * BYTES_PER_PIXEL;
+        if pixels.len() != len {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "pixel data does not match the icon size",
+            ));
         }
+        let mut buffer = ShmBuffer::create(len)?;
+        buffer.as_mut_slice()[..len].copy_from_slice(pixels);
+        let buffer = SharedFd::new(buffer.share()?);
+        self.send_window_request(Capabilities::WINDOW_PROPERTIES, "window properties", |window_id| {
+            Packet::SetIcon {
+                window_id,
+                width,
+                height,
+                buffer,
+            }
+        })
+    }
+
+    /// Removes the icon from the title bar.
+    pub fn remove_icon(&self) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_PROPERTIES, "window properties", |window_id| {
+            Packet::RemoveIcon { window_id }
+        })
+    }
+
+    /// Sets whether the user can resize the window by dragging its edges.
+    pub fn set_resizable(&self, resizable: bool) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_PROPERTIES, "window properties", |window_id| {
+            Packet::SetResizable {
+                window_id,
+                resizable,
+            }
+        })
+    }
+
+    /// Sets whether the compositor draws a title bar and frame around the
+    /// window.
+    pub fn set_decorations(&self, decorated: bool) -> io::Result<()> {
+        self.send_window_request(Capabilities::WINDOW_PROPERTIES, "window properties", |window_id| {
+            Packet::SetDecorations {
+                window_id,
+                decorated,
+            }
+        })
+    }
+
+    /// Sends a request about the window, if it is still open and the
+    /// compositor supports `capability`.
+    fn send_window_request(
+        &self,
+        capability: Capabilities,
+        feature: &str,
+        request: impl FnOnce(u64) -> Packet,
+    ) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        open_window(&mut state, self.window_id)?;
+        check_supported(&state, capability, feature)?;
+        send_packet(&mut state, &request(self.window_id))
+    }
+
+    /// Size of the client area, or `None` once the window is closed.
+    pub fn get_size(&self) -> Option<(ScreenSize, ScreenSize)> {
+        self.read(|window| window.size)
+    }
+
+    /// Whether the window is maximized, minimized or fullscreen, as last
+    /// reported by the compositor, or `None` once it is closed.
+    pub fn get_state(&self) -> Option<WindowState> {
+        self.read(|window| window.window_state)
+    }
+
+    /// Position on screen, or `None` once the window is closed.
+    pub fn get_position(&self) -> Option<(ScreenSize, ScreenSize)> {
+        self.read(|window| window.position)
+    }
+
+    fn read<T>(&self, field: impl FnOnce(&WindowData) -> T) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        state.windows.get(&self.window_id).filter(|window| !window.already_closed).map(field)
+    }
 
-        // Unregister the client
-        registry.unregister_client(&self.state);
-        *self.state.lock().unwrap() = None;
+    /// Closes the window. The compositor keeps a window whose close button
+    /// was clicked until this is called.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.windows.remove(&self.window_id).is_none() {
+            return;
+        }
+        let close_packet = Packet::Close {
+            window_id: self.window_id,
+        };
+        let _ = send_packet(&mut state, &close_packet);
     }
 }
 
-impl Drop for Client {
+impl Drop for Window {
     fn drop(&mut self) {
-        self.close_window();
+        self.close();
+    }
+}
+
+/// Works out where a packet from the compositor goes, updating the window
+/// state it carries on the way. Without `Capabilities::MULTI_WINDOW` window
+/// events arrive bare and are for the only window.
+fn route_packet(state: &mut ConnectionState, packet: Packet) -> io::Result<Routed> {
+    let (window_id, event) = match packet {
+        Packet::WindowEvent { window_id, event } => (window_id, event),
+        packet if !state.negotiated.supports(Capabilities::MULTI_WINDOW) => {
+            match (state.windows.keys().next().copied(), Event::try_from(packet)) {
+                (Some(window_id), Ok(event)) => (window_id, event),
+                (_, Ok(event)) => return Ok(Routed::Connection(event.into())),
+                (_, Err(packet)) => return Ok(Routed::Connection(packet)),
+            }
+        }
+        packet => return Ok(Routed::Connection(packet)),
+    };
+    let negotiated = state.negotiated;
+    let Some(window) = state.windows.get_mut(&window_id) else {
+        return Ok(Routed::Handled);
+    };
+    match event {
+        Event::Resize { width, height } => window.size = (width, height),
+        Event::Position { x, y } => window.position = (x, y),
+        Event::Configure {
+            serial,
+            width,
+            height,
+            state: window_state,
+        } => {
+            window.size = (width, height);
+            window.window_state = window_state;
+            // Frames painted from now on use the new size
+            if negotiated.supports(Capabilities::CONFIGURE_ACK) {
+                send_packet(state, &Packet::AckConfigure { window_id, serial })?;
+            }
+        }
+        event => {
+            if event == Event::Closed {
+                window.already_closed = true;
+            }
+            return Ok(Routed::Window(window.callback.clone(), event.into()));
+        }
+    }
+    Ok(Routed::Handled)
+}
+
+/// Runs a window callback with the connection unlocked and sends its
+/// answer, or hands back a packet meant for the connection.
+fn deliver(state: &Mutex<ConnectionState>, routed: Routed) -> io::Result<Option<Packet>> {
+    match routed {
+        Routed::Window(callback, event) => {
+            if let Some(response) = callback(event) {
+                send_packet(&mut state.lock().unwrap(), &response)?;
+            }
+            Ok(None)
+        }
+        Routed::Connection(packet) => Ok(Some(packet)),
+        Routed::Handled => Ok(None),
+    }
+}
+
+/// The window's state, unless it has been closed by either side. A window
+/// the compositor closed only waits for `Window::close`.
+fn open_window(state: &mut ConnectionState, window_id: u64) -> io::Result<&mut WindowData> {
+    state
+        .windows
+        .get_mut(&window_id)
+        .filter(|window| !window.already_closed)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotConnected, "window is closed"))
+}
+
+/// Fails unless the compositor supports `capability`. `feature` names it in
+/// the error.
+fn check_supported(state: &ConnectionState, capability: Capabilities, feature: &str) -> io::Result<()> {
+    if !state.negotiated.supports(capability) {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("compositor does not support {}", feature),
+        ));
     }
+    Ok(())
 }
 
-fn receive_packet(stream: &mut UnixStream) -> io::Result<Packet> { 
-    let  ret = libprotocol::receive_packet(stream);
+fn receive_packet(state: &mut ConnectionState) -> io::Result<Packet> {
+    let ret = state.codec.receive(&state.stream).map_err(io::Error::from);
     if let Ok(packet) = &ret {
         println!("LibCrystalMatrix: Received packet: {:?}", packet);
     }
     ret
 }
 
-fn send_packet(stream: &mut UnixStream, packet: &Packet) -> io::Result<()> {
+fn send_packet(state: &mut ConnectionState, packet: &Packet) -> io::Result<()> {
     println!("LibCrystalMatrix: Sending packet {:?}", packet);
-    libprotocol::send_packet(stream, packet)
+    state.codec.send(&state.stream, packet)
 }
@@ -0,0 +1,391 @@
+//! Length-prefixed packet framing that survives partial reads and writes.
+//!
+//! Every frame is a little-endian `u32` payload length followed by the
+//! bincode payload. Descriptors ride along as `SCM_RIGHTS` data on the first
+//! byte of their frame. A [`PacketCodec`] keeps whatever part of a frame has
+//! arrived so far, so a non-blocking socket can return `WouldBlock` at any
+//! point without desyncing the stream.
+//!
+//! The kernel ends a read right after the message that carried descriptors,
+//! so descriptors belong to the frame that ends at or after the end of the
+//! read they arrived with. Each decoded frame takes only its own, and any the
+//! packet has no use for are closed.
+//!
+//! Frames longer than the codec's maximum frame size are refused before any
+//! of the payload is buffered, and bincode decodes under the same limit so a
+//! hostile length field inside the payload cannot force a huge allocation.
+
+use crate::{ancillary, Packet};
+use bincode::Options;
+use std::collections::VecDeque;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::{fmt, io};
+
+const HEADER_LEN: usize = 4;
+/// Bytes asked of the socket per read.
+const READ_CHUNK: usize = 64 * 1024;
+/// Most received descriptors held for frames that have not fully arrived.
+const MAX_QUEUED_FDS: usize = 64;
+/// Largest payload accepted unless the codec is configured otherwise.
+///
+/// Pixels travel in shared memory, so real packets stay far below this.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Why a frame could not be read or decoded.
+#[derive(Debug)]
+pub enum CodecError {
+    /// The socket failed, would block, or hit end of file.
+    Io(io::Error),
+    /// The length prefix exceeds the codec's limit. The stream cannot be
+    /// resynced after this, so the connection should be dropped.
+    FrameTooLarge { size: usize, limit: usize },
+    /// The payload is not a valid packet. The frame has been discarded and
+    /// the next one can still be read.
+    Malformed(String),
+    /// A packet that carries a descriptor arrived without one.
+    MissingFd(&'static str),
+    /// More descriptors arrived than any pending frame could use.
+    TooManyFds { limit: usize },
+}
+
+impl CodecError {
+    /// Whether the stream is still usable after this error.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, CodecError::Malformed(_) | CodecError::MissingFd(_))
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "I/O error: {}", e),
+            CodecError::FrameTooLarge { size, limit } => {
+                write!(f, "frame of {} bytes exceeds the {} byte limit", size, limit)
+            }
+            CodecError::Malformed(e) => write!(f, "malformed packet: {}", e),
+            CodecError::MissingFd(packet) => write!(f, "{} arrived without a descriptor", packet),
+            CodecError::TooManyFds { limit } => {
+                write!(f, "more than {} descriptors queued without a frame", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodecError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+impl From<CodecError> for io::Error {
+    fn from(e: CodecError) -> Self {
+        match e {
+            CodecError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+struct OutgoingFrame {
+    data: Vec<u8>,
+    written: usize,
+    // Sent with the first chunk of `data`, then dropped
+    fds: Vec<OwnedFd>,
+}
+
+/// Per-connection framing state.
+pub struct PacketCodec {
+    read_buf: Vec<u8>,
+    /// Stream offset of the first byte of `read_buf`.
+    read_pos: u64,
+    /// Received descriptors, each with the stream offset at which the read
+    /// that carried it ended.
+    read_fds: VecDeque<(u64, OwnedFd)>,
+    write_queue: VecDeque<OutgoingFrame>,
+    max_frame_size: usize,
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        Self::with_max_frame_size(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl PacketCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a codec that refuses payloads longer than `max_frame_size`
+    /// in either direction.
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        Self {
+            read_buf: Vec::new(),
+            read_pos: 0,
+            read_fds: VecDeque::new(),
+            write_queue: VecDeque::new(),
+            max_frame_size: max_frame_size.min(u32::MAX as usize),
+        }
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Returns the next whole packet, reading from `stream` as needed.
+    ///
+    /// On a non-blocking socket this fails with `WouldBlock` once the socket
+    /// is drained; the partial frame stays buffered for the next call. A
+    /// closed socket yields `UnexpectedEof`.
+    pub fn receive(&mut self, stream: &UnixStream) -> Result<Packet, CodecError> {
+        loop {
+            if let Some(packet) = self.decode()? {
+                return Ok(packet);
+            }
+            if self.fill(stream)? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    /// Serializes `packet` onto the write queue without touching the socket.
+    pub fn queue(&mut self, packet: &Packet) -> io::Result<()> {
+        let payload = self.bincode().serialize(packet).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, CodecError::Malformed(e.to_string()))
+        })?;
+        // The bincode limit keeps the payload within `max_frame_size`
+        let len = payload.len() as u32;
+
+        let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&payload);
+
+        let fds = packet
+            .fds()
+            .into_iter()
+            .map(|fd| fd.try_clone_to_owned())
+            .collect::<io::Result<_>>()?;
+        self.write_queue.push_back(OutgoingFrame {
+            data,
+            written: 0,
+            fds,
+        });
+        Ok(())
+    }
+
+    /// Writes queued frames until the queue is empty or the socket is full.
+    ///
+    /// Returns `false` if data is still pending, in which case the caller
+    /// should flush again once the socket is writable.
+    pub fn flush(&mut self, stream: &UnixStream) -> io::Result<bool> {
+        while let Some(frame) = self.write_queue.front_mut() {
+            let fds: Vec<_> = frame.fds.iter().map(|fd| fd.as_fd()).collect();
+            match ancillary::send_with_fds(stream, &frame.data[frame.written..], &fds) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    frame.written += n;
+                    frame.fds.clear();
+                    if frame.written == frame.data.len() {
+                        self.write_queue.pop_front();
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Queues `packet` and writes as much as the socket takes right away.
+    pub fn send(&mut self, stream: &UnixStream, packet: &Packet) -> io::Result<()> {
+        self.queue(packet)?;
+        self.flush(stream)?;
+        Ok(())
+    }
+
+    pub fn has_pending_writes(&self) -> bool {
+        !self.write_queue.is_empty()
+    }
+
+    /// Wire format of the payload, bounded by the frame limit.
+    fn bincode(&self) -> impl Options {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .with_limit(self.max_frame_size as u64)
+    }
+
+    /// Reads one chunk from `stream`, returning the byte count (0 on EOF).
+    fn fill(&mut self, stream: &UnixStream) -> Result<usize, CodecError> {
+        let start = self.read_buf.len();
+        self.read_buf.resize(start + READ_CHUNK, 0);
+        let mut fds = VecDeque::new();
+        let result = ancillary::recv_with_fds(stream, &mut self.read_buf[start..], &mut fds);
+        let read = *result.as_ref().unwrap_or(&0);
+        self.read_buf.truncate(start + read);
+        self.queue_fds(fds)?;
+        Ok(result?)
+    }
+
+    /// Holds descriptors that came with the read that just filled the buffer.
+    fn queue_fds(&mut self, fds: VecDeque<OwnedFd>) -> Result<(), CodecError> {
+        let end = self.read_pos + self.read_buf.len() as u64;
+        self.read_fds.extend(fds.into_iter().map(|fd| (end, fd)));
+        if self.read_fds.len() > MAX_QUEUED_FDS {
+            self.read_fds.clear();
+            return Err(CodecError::TooManyFds {
+                limit: MAX_QUEUED_FDS,
+            });
+        }
+        Ok(())
+    }
+
+    /// Pops a complete frame off the read buffer, if one has arrived.
+    fn decode(&mut self) -> Result<Option<Packet>, CodecError> {
+        let Some(header) = self.read_buf.get(..HEADER_LEN) else {
+            return Ok(None);
+        };
+        let len = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+        if len > self.max_frame_size {
+            return Err(CodecError::FrameTooLarge {
+                size: len,
+                limit: self.max_frame_size,
+            });
+        }
+        if self.read_buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        // Drop the frame and its descriptors even if it fails to decode so
+        // the next one can be read
+        let frame: Vec<u8> = self.read_buf.drain(..HEADER_LEN + len).skip(HEADER_LEN).collect();
+        self.read_pos += (HEADER_LEN + len) as u64;
+        let mut fds = VecDeque::new();
+        while self.read_fds.front().is_some_and(|(end, _)| *end <= self.read_pos) {
+            fds.extend(self.read_fds.pop_front().map(|(_, fd)| fd));
+        }
+        let mut packet: Packet = self
+            .bincode()
+            .deserialize(&frame)
+            .map_err(|e| CodecError::Malformed(e.to_string()))?;
+        // Descriptors the packet doesn't take are closed here
+        packet.attach_fds(&mut fds)?;
+        Ok(Some(packet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SharedFd;
+    use std::io::Read;
+    use std::os::fd::{AsRawFd, BorrowedFd};
+
+    /// Writes `packet` as one frame with `fds` attached, whatever the packet.
+    fn send_frame(stream: &UnixStream, packet: &Packet, fds: &[BorrowedFd<'_>]) {
+        let payload = PacketCodec::new().bincode().serialize(packet).unwrap();
+        let mut data = (payload.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&payload);
+        assert_eq!(ancillary::send_with_fds(stream, &data, fds).unwrap(), data.len());
+    }
+
+    fn paint() -> Packet {
+        Packet::Paint {
+            window_id: 1,
+            width: 1,
+            height: 1,
+            buffer: SharedFd::default(),
+        }
+    }
+
+    /// Whether the write end of `reader`'s pipe is still open anywhere.
+    fn writer_open(reader: &mut io::PipeReader) -> bool {
+        unsafe {
+            let flags = libc::fcntl(reader.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+        match reader.read(&mut [0]) {
+            Ok(0) => false,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => true,
+            other => panic!("unexpected pipe read: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fds_sent_with_other_packets_are_closed() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (mut reader, writer) = io::pipe().unwrap();
+        send_frame(&client, &Packet::Close { window_id: 1 }, &[writer.as_fd()]);
+        drop(writer);
+        send_frame(&client, &paint(), &[]);
+
+        let mut codec = PacketCodec::new();
+        assert_eq!(codec.receive(&server).unwrap(), Packet::Close { window_id: 1 });
+        assert!(!writer_open(&mut reader));
+        assert!(matches!(codec.receive(&server), Err(CodecError::MissingFd("Paint"))));
+    }
+
+    #[test]
+    fn fds_stay_with_their_frame() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (mut stray_reader, stray) = io::pipe().unwrap();
+        let (mut buffer_reader, buffer) = io::pipe().unwrap();
+        send_frame(&client, &Packet::Close { window_id: 1 }, &[stray.as_fd()]);
+        send_frame(&client, &paint(), &[buffer.as_fd()]);
+        drop((stray, buffer));
+
+        let mut codec = PacketCodec::new();
+        codec.receive(&server).unwrap();
+        let packet = codec.receive(&server).unwrap();
+        assert!(!writer_open(&mut stray_reader));
+        assert!(writer_open(&mut buffer_reader));
+        drop(packet);
+        assert!(!writer_open(&mut buffer_reader));
+    }
+
+    #[test]
+    fn nested_window_events_are_malformed() {
+        let event = Packet::WindowEvent {
+            window_id: 1,
+            event: crate::Event::Closed,
+        };
+        let encoded = PacketCodec::new().bincode().serialize(&event).unwrap();
+        // Variant tag and window ID, over and over, as if events could nest
+        let header = &encoded[..encoded.len() - 4];
+        let payload = header.repeat(DEFAULT_MAX_FRAME_SIZE / header.len());
+        let (client, server) = UnixStream::pair().unwrap();
+        let writer = std::thread::spawn(move || {
+            let mut data = (payload.len() as u32).to_le_bytes().to_vec();
+            data.extend_from_slice(&payload);
+            io::Write::write_all(&mut &client, &data).unwrap();
+        });
+
+        let result = PacketCodec::new().receive(&server);
+        writer.join().unwrap();
+        assert!(matches!(result, Err(CodecError::Malformed(_))));
+    }
+
+    #[test]
+    fn too_many_fds_for_a_pending_frame_are_refused() {
+        let (client, server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let (_reader, writer) = io::pipe().unwrap();
+        let fds = [writer.as_fd(); 16];
+        // A frame header followed by single bytes, each carrying descriptors
+        ancillary::send_with_fds(&client, &100u32.to_le_bytes(), &fds).unwrap();
+        for _ in 0..MAX_QUEUED_FDS / fds.len() {
+            ancillary::send_with_fds(&client, &[0], &fds).unwrap();
+        }
+
+        let result = PacketCodec::new().receive(&server);
+        assert!(matches!(result, Err(CodecError::TooManyFds { limit: MAX_QUEUED_FDS })));
+    }
+}
@@ -1,13 +1,40 @@
 use serde::{Deserialize, Serialize};
-use std::{fmt, io};
-use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
+use std::collections::VecDeque;
+use std::fmt;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::sync::Arc;
+
+mod ancillary;
+mod codec;
+pub mod shm;
+mod socket;
+mod version;
+
+pub use codec::{CodecError, PacketCodec, DEFAULT_MAX_FRAME_SIZE};
+pub use socket::{control_socket_path, lock_path, socket_path, DEFAULT_DISPLAY, DISPLAY_ENV};
+pub use version::{
+    negotiate, Capabilities, Negotiated, CONTROL_CAPABILITIES, PROTOCOL_CAPABILITIES,
+};
 
 pub type ScreenSize = i32;
-pub const WINDOW_UNIX_SOCKET_NAME: &'static str = "/tmp/prism_comp";
-pub const PROTOCOL_VERSION: (u8, u8, u8) = (0, 0, 1);
+/// Largest width or height the compositor accepts for a window or buffer.
+pub const MAX_SURFACE_SIZE: ScreenSize = 8192;
+/// Largest width or height of a custom cursor image.
+pub const MAX_CURSOR_SIZE: ScreenSize = 256;
+/// Largest width or height of a window icon.
+pub const MAX_ICON_SIZE: ScreenSize = 256;
+/// Longest window title or app ID, in bytes.
+pub const MAX_TITLE_LENGTH: usize = 1024;
+pub const PROTOCOL_VERSION: (u8, u8, u8) = (0, 1, 0);
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Packet {
+    /// Opens every connection in both directions; see [`negotiate`].
+    Hello {
+        major: u8,
+        minor: u8,
+        patch: u8,
+        capabilities: Capabilities,
+    },
     // TO Compositor
     Create {
         width: ScreenSize,
@@ -17,14 +44,22 @@ pub enum Packet {
     Close {
         window_id: u64,
     },
+    /// New window contents in the attached shared-memory `buffer`,
+    /// `width * height` pixels of tightly packed RGBA. The compositor draws
+    /// the window from the buffer until the next `Paint`.
     Paint {
         window_id: u64,
+        width: ScreenSize,
+        height: ScreenSize,
+        #[serde(skip)]
+        buffer: SharedFd,
     },
-    RequestAPIVersion,
     //TO Client
     CreateSuccess {
         window_id: u64,
     },
+    /// The window was closed, or the user clicked its close button. The
+    /// window stays open until the client answers the latter with `Close`.
     Closed,
     MouseEnter,
     MouseLeave,
@@ -61,11 +96,379 @@ pub enum Packet {
     Suspend,
     Resume,
     DemandPaint,
-    APIVersion {
-        major: u8,
-        minor: u8,
-        patch: u8,
+    /// The compositor rejected a request; `request` names the packet.
+    Error {
+        request: String,
+        code: ErrorCode,
+        message: String,
+    },
+    // TO Compositor, with `Capabilities::CURSORS`
+    /// Cursor to show from the theme while the pointer is over the window.
+    SetCursor {
+        window_id: u64,
+        shape: CursorShape,
+    },
+    /// Cursor image to show while the pointer is over the window, as
+    /// `width * height` RGBA pixels in the attached shared-memory `buffer`.
+    /// The hotspot is the pixel that sits under the pointer.
+    SetCustomCursor {
+        window_id: u64,
+        width: ScreenSize,
+        height: ScreenSize,
+        hotspot_x: ScreenSize,
+        hotspot_y: ScreenSize,
+        #[serde(skip)]
+        buffer: SharedFd,
+    },
+    // TO Compositor, with `Capabilities::WALLPAPER`
+    /// Changes the desktop background. `image` is an optional attached PNG
+    /// or JPEG file, read from its start; `fill` shows wherever the image
+    /// leaves the screen uncovered, or everywhere without an image.
+    SetWallpaper {
+        mode: WallpaperMode,
+        fill: BackgroundFill,
+        #[serde(skip)]
+        image: SharedFd,
+    },
+    // TO Client, with `Capabilities::FOCUS_EVENTS`
+    /// The window now receives keyboard input.
+    FocusIn,
+    /// The window no longer receives keyboard input.
+    FocusOut,
+    // TO Compositor, with `Capabilities::SIZE_LIMITS`
+    /// Size range the user can resize the window's client area within.
+    /// A `None` maximum leaves that dimension unlimited.
+    SetSizeLimits {
+        window_id: u64,
+        min_width: ScreenSize,
+        min_height: ScreenSize,
+        max_width: Option<ScreenSize>,
+        max_height: Option<ScreenSize>,
+    },
+    // TO Compositor, with `Capabilities::WINDOW_STATES`
+    /// Fills the screen with the window, keeping its decorations.
+    Maximize {
+        window_id: u64,
+    },
+    /// Returns a maximized window to its earlier geometry.
+    Unmaximize {
+        window_id: u64,
+    },
+    /// Hides the window until the user or client brings it back.
+    Minimize {
+        window_id: u64,
+    },
+    /// Covers the whole screen with the client area and no decorations.
+    Fullscreen {
+        window_id: u64,
+    },
+    /// Returns a fullscreen window to its earlier geometry.
+    Unfullscreen {
+        window_id: u64,
+    },
+    // TO Client, with `Capabilities::WINDOW_STATES`
+    /// The window's client area size or state changed. Peers without the
+    /// capability are sent a `Resize` instead. With
+    /// `Capabilities::CONFIGURE_ACK` the client answers with `AckConfigure`
+    /// once it will paint at the new size.
+    Configure {
+        serial: u32,
+        width: ScreenSize,
+        height: ScreenSize,
+        state: WindowState,
+    },
+    // TO Compositor, with `Capabilities::CONFIGURE_ACK`
+    /// The next `Paint` of the window is at the size of the `Configure` with
+    /// this serial. Until it arrives the compositor keeps showing the last
+    /// frame and ignores frames at other sizes. Acknowledging a serial also
+    /// covers every earlier one.
+    AckConfigure {
+        window_id: u64,
+        serial: u32,
+    },
+    // TO Compositor, with `Capabilities::WINDOW_PROPERTIES`. The size range
+    // is set with `SetSizeLimits`.
+    /// Text shown in the title bar, at most `MAX_TITLE_LENGTH` bytes. `None`
+    /// shows the app ID instead, if there is one.
+    SetTitle {
+        window_id: u64,
+        title: Option<String>,
+    },
+    /// Identifies the application the window belongs to, such as
+    /// `org.example.Editor`, in at most `MAX_TITLE_LENGTH` bytes.
+    SetAppId {
+        window_id: u64,
+        app_id: Option<String>,
+    },
+    /// Icon shown in the title bar, as `width * height` RGBA pixels in the
+    /// attached shared-memory `buffer`, at most `MAX_ICON_SIZE` on either
+    /// side. The compositor scales it to fit.
+    SetIcon {
+        window_id: u64,
+        width: ScreenSize,
+        height: ScreenSize,
+        #[serde(skip)]
+        buffer: SharedFd,
+    },
+    /// Removes the icon from the title bar.
+    RemoveIcon {
+        window_id: u64,
     },
+    /// Whether the user can resize the window by dragging its edges.
+    SetResizable {
+        window_id: u64,
+        resizable: bool,
+    },
+    /// Whether the compositor draws a title bar and frame around the window.
+    SetDecorations {
+        window_id: u64,
+        decorated: bool,
+    },
+    // TO Client, with `Capabilities::MULTI_WINDOW`
+    /// An event for one of the client's windows. Peers without the
+    /// capability have a single window and get the bare `event` as the
+    /// packet of the same name.
+    WindowEvent {
+        window_id: u64,
+        event: Event,
+    },
+}
+
+/// States a window can be in on top of its normal geometry.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WindowState {
+    pub maximized: bool,
+    pub minimized: bool,
+    pub fullscreen: bool,
+}
+
+/// The client-bound packets that concern a single window, carried by
+/// `Packet::WindowEvent`.
+///
+/// Kept apart from `Packet` so a window event cannot nest another one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Event {
+    Closed,
+    MouseEnter,
+    MouseLeave,
+    MousePosition {
+        x: ScreenSize,
+        y: ScreenSize,
+    },
+    MouseDown {
+        button: MouseButton,
+        x: ScreenSize,
+        y: ScreenSize,
+    },
+    MouseUp {
+        button: MouseButton,
+        x: ScreenSize,
+        y: ScreenSize,
+    },
+    KeyDown {
+        key: KeyCode,
+        modifiers: Modifiers,
+    },
+    KeyUp {
+        key: KeyCode,
+        modifiers: Modifiers,
+    },
+    Position {
+        x: ScreenSize,
+        y: ScreenSize,
+    },
+    Resize {
+        width: ScreenSize,
+        height: ScreenSize,
+    },
+    Suspend,
+    Resume,
+    DemandPaint,
+    FocusIn,
+    FocusOut,
+    Configure {
+        serial: u32,
+        width: ScreenSize,
+        height: ScreenSize,
+        state: WindowState,
+    },
+}
+
+impl From<Event> for Packet {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::Closed => Packet::Closed,
+            Event::MouseEnter => Packet::MouseEnter,
+            Event::MouseLeave => Packet::MouseLeave,
+            Event::MousePosition { x, y } => Packet::MousePosition { x, y },
+            Event::MouseDown { button, x, y } => Packet::MouseDown { button, x, y },
+            Event::MouseUp { button, x, y } => Packet::MouseUp { button, x, y },
+            Event::KeyDown { key, modifiers } => Packet::KeyDown { key, modifiers },
+            Event::KeyUp { key, modifiers } => Packet::KeyUp { key, modifiers },
+            Event::Position { x, y } => Packet::Position { x, y },
+            Event::Resize { width, height } => Packet::Resize { width, height },
+            Event::Suspend => Packet::Suspend,
+            Event::Resume => Packet::Resume,
+            Event::DemandPaint => Packet::DemandPaint,
+            Event::FocusIn => Packet::FocusIn,
+            Event::FocusOut => Packet::FocusOut,
+            Event::Configure {
+                serial,
+                width,
+                height,
+                state,
+            } => Packet::Configure {
+                serial,
+                width,
+                height,
+                state,
+            },
+        }
+    }
+}
+
+impl TryFrom<Packet> for Event {
+    /// The packet back, if it is not about a single window.
+    type Error = Packet;
+
+    fn try_from(packet: Packet) -> Result<Self, Packet> {
+        Ok(match packet {
+            Packet::Closed => Event::Closed,
+            Packet::MouseEnter => Event::MouseEnter,
+            Packet::MouseLeave => Event::MouseLeave,
+            Packet::MousePosition { x, y } => Event::MousePosition { x, y },
+            Packet::MouseDown { button, x, y } => Event::MouseDown { button, x, y },
+            Packet::MouseUp { button, x, y } => Event::MouseUp { button, x, y },
+            Packet::KeyDown { key, modifiers } => Event::KeyDown { key, modifiers },
+            Packet::KeyUp { key, modifiers } => Event::KeyUp { key, modifiers },
+            Packet::Position { x, y } => Event::Position { x, y },
+            Packet::Resize { width, height } => Event::Resize { width, height },
+            Packet::Suspend => Event::Suspend,
+            Packet::Resume => Event::Resume,
+            Packet::DemandPaint => Event::DemandPaint,
+            Packet::FocusIn => Event::FocusIn,
+            Packet::FocusOut => Event::FocusOut,
+            Packet::Configure {
+                serial,
+                width,
+                height,
+                state,
+            } => Event::Configure {
+                serial,
+                width,
+                height,
+                state,
+            },
+            packet => return Err(packet),
+        })
+    }
+}
+
+/// How a wallpaper image is fitted to the screen.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum WallpaperMode {
+    /// Scaled to cover the whole screen, cropping what overflows
+    Fill,
+    /// Scaled to fit inside the screen, with the fill around it
+    Fit,
+    /// Scaled to the screen size, ignoring its aspect ratio
+    Stretch,
+    /// Unscaled in the middle of the screen
+    Center,
+    /// Repeated unscaled from the top-left corner
+    Tile,
+}
+
+impl WallpaperMode {
+    pub const ALL: [WallpaperMode; 5] = [
+        WallpaperMode::Fill,
+        WallpaperMode::Fit,
+        WallpaperMode::Stretch,
+        WallpaperMode::Center,
+        WallpaperMode::Tile,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WallpaperMode::Fill => "fill",
+            WallpaperMode::Fit => "fit",
+            WallpaperMode::Stretch => "stretch",
+            WallpaperMode::Center => "center",
+            WallpaperMode::Tile => "tile",
+        }
+    }
+
+    /// The mode called `name`, as returned by [`WallpaperMode::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+/// Colour painted behind the wallpaper image, in 8-bit RGB.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum BackgroundFill {
+    Solid([u8; 3]),
+    /// Fades from `top` at the top edge of the screen to `bottom`
+    VerticalGradient { top: [u8; 3], bottom: [u8; 3] },
+}
+
+/// Cursors a client can ask for by name.
+///
+/// Themes that lack a shape show the default cursor instead.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum CursorShape {
+    Default,
+    /// A link or other clickable element
+    Pointer,
+    /// Selectable text
+    Text,
+    Crosshair,
+    Move,
+    /// The application is busy
+    Wait,
+    NotAllowed,
+    EwResize,
+    NsResize,
+    NwseResize,
+    NeswResize,
+}
+
+impl CursorShape {
+    /// The CSS `cursor` keyword that names this shape in cursor themes.
+    pub fn name(self) -> &'static str {
+        match self {
+            CursorShape::Default => "default",
+            CursorShape::Pointer => "pointer",
+            CursorShape::Text => "text",
+            CursorShape::Crosshair => "crosshair",
+            CursorShape::Move => "move",
+            CursorShape::Wait => "wait",
+            CursorShape::NotAllowed => "not-allowed",
+            CursorShape::EwResize => "ew-resize",
+            CursorShape::NsResize => "ns-resize",
+            CursorShape::NwseResize => "nwse-resize",
+            CursorShape::NeswResize => "nesw-resize",
+        }
+    }
+}
+
+/// Why the compositor rejected a request.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// No window with the given ID exists
+    UnknownWindow,
+    /// The window belongs to another connection
+    NotOwner,
+    /// A width or height is not positive or exceeds `MAX_SURFACE_SIZE`
+    InvalidSize,
+    /// A shared buffer is missing, too small or not sealed
+    InvalidBuffer,
+    /// The packet is not something a client may send
+    InvalidRequest,
+    /// The client's major protocol version differs from the compositor's
+    IncompatibleVersion,
+    /// An image file could not be read or decoded
+    InvalidImage,
 }
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum MouseButton {
@@ -94,40 +497,166 @@ pub enum KeyCode {
     Function(u8),
 }
 
-fn deserialize(packet: &Vec<u8>) -> bincode::Result<Packet> {
-    bincode::deserialize(&packet)
-}
-fn serialize(packet: &Packet) -> bincode::Result<Vec<u8>> {
-    bincode::serialize(&packet)
+/// A file descriptor that travels next to a packet as `SCM_RIGHTS` data.
+///
+/// It is not part of the serialized payload: `PacketCodec` attaches it to the
+/// frame and fills it back in from the ancillary data on the other side.
+#[derive(Debug, Clone, Default)]
+pub struct SharedFd(Option<Arc<OwnedFd>>);
+
+impl SharedFd {
+    pub fn new(fd: OwnedFd) -> Self {
+        Self(Some(Arc::new(fd)))
+    }
+
+    pub fn as_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.0.as_ref().map(|fd| fd.as_fd())
+    }
 }
 
-pub fn receive_packet(stream: &mut UnixStream) -> io::Result<Packet> {
-    let mut size_buf = [0u8; 4];
-    stream.read_exact(&mut size_buf)?;
-    let size = u32::from_le_bytes(size_buf) as usize;
-    let mut packet_buf = vec![0u8; size];
-    stream.read_exact(&mut packet_buf)?;
-    bincode::deserialize(&packet_buf)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+impl PartialEq for SharedFd {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => a.as_raw_fd() == b.as_raw_fd(),
+            (None, None) => true,
+            _ => false,
+        }
+    }
 }
 
-pub fn send_packet(stream: &mut UnixStream, packet: &Packet) -> io::Result<()> {
-    let data = serialize(packet)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+impl Eq for SharedFd {}
 
-    let size = (data.len() as u32).to_le_bytes();
-    stream.write_all(&size)?;
-    stream.write_all(&data)?;
-    stream.flush()?;
+impl Packet {
+    /// Name of the packet variant, as reported in `Packet::Error`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Packet::Hello { .. } => "Hello",
+            Packet::Create { .. } => "Create",
+            Packet::Close { .. } => "Close",
+            Packet::Paint { .. } => "Paint",
+            Packet::CreateSuccess { .. } => "CreateSuccess",
+            Packet::Closed => "Closed",
+            Packet::MouseEnter => "MouseEnter",
+            Packet::MouseLeave => "MouseLeave",
+            Packet::MousePosition { .. } => "MousePosition",
+            Packet::MouseDown { .. } => "MouseDown",
+            Packet::MouseUp { .. } => "MouseUp",
+            Packet::KeyDown { .. } => "KeyDown",
+            Packet::KeyUp { .. } => "KeyUp",
+            Packet::Position { .. } => "Position",
+            Packet::Resize { .. } => "Resize",
+            Packet::Suspend => "Suspend",
+            Packet::Resume => "Resume",
+            Packet::DemandPaint => "DemandPaint",
+            Packet::Error { .. } => "Error",
+            Packet::SetCursor { .. } => "SetCursor",
+            Packet::SetCustomCursor { .. } => "SetCustomCursor",
+            Packet::SetWallpaper { .. } => "SetWallpaper",
+            Packet::FocusIn => "FocusIn",
+            Packet::FocusOut => "FocusOut",
+            Packet::SetSizeLimits { .. } => "SetSizeLimits",
+            Packet::Maximize { .. } => "Maximize",
+            Packet::Unmaximize { .. } => "Unmaximize",
+            Packet::Minimize { .. } => "Minimize",
+            Packet::Fullscreen { .. } => "Fullscreen",
+            Packet::Unfullscreen { .. } => "Unfullscreen",
+            Packet::Configure { .. } => "Configure",
+            Packet::AckConfigure { .. } => "AckConfigure",
+            Packet::SetTitle { .. } => "SetTitle",
+            Packet::SetAppId { .. } => "SetAppId",
+            Packet::SetIcon { .. } => "SetIcon",
+            Packet::RemoveIcon { .. } => "RemoveIcon",
+            Packet::SetResizable { .. } => "SetResizable",
+            Packet::SetDecorations { .. } => "SetDecorations",
+            Packet::WindowEvent { .. } => "WindowEvent",
+        }
+    }
 
-    Ok(())
-}
+    /// Capability a client has to have negotiated to be sent this event.
+    pub fn event_capability(&self) -> Capabilities {
+        match self {
+            Packet::MouseEnter
+            | Packet::MouseLeave
+            | Packet::MousePosition { .. }
+            | Packet::MouseDown { .. }
+            | Packet::MouseUp { .. }
+            | Packet::KeyDown { .. }
+            | Packet::KeyUp { .. } => Capabilities::INPUT_EVENTS,
+            Packet::FocusIn | Packet::FocusOut => Capabilities::FOCUS_EVENTS,
+            Packet::Configure { .. } => Capabilities::WINDOW_STATES,
+            Packet::WindowEvent { event, .. } => Packet::from(*event).event_capability(),
+            _ => Capabilities::empty(),
+        }
+    }
+
+    /// The form of this event a peer with the given negotiated features
+    /// understands, or `None` if it should not be sent at all.
+    pub fn for_peer(self, negotiated: &Negotiated) -> Option<Packet> {
+        match self {
+            Packet::WindowEvent { window_id, event } => {
+                let packet = Packet::from(event).for_peer(negotiated)?;
+                if negotiated.supports(Capabilities::MULTI_WINDOW) {
+                    // Downgrades only ever turn one window event into another
+                    let event = Event::try_from(packet).ok()?;
+                    Some(Packet::WindowEvent { window_id, event })
+                } else {
+                    Some(packet)
+                }
+            }
+            Packet::Configure { width, height, .. }
+                if !negotiated.supports(Capabilities::WINDOW_STATES) =>
+            {
+                Some(Packet::Resize { width, height })
+            }
+            packet if negotiated.supports(packet.event_capability()) => Some(packet),
+            _ => None,
+        }
+    }
 
+    /// Descriptors that have to be sent along with this packet.
+    pub fn fds(&self) -> Vec<BorrowedFd<'_>> {
+        match self {
+            Packet::Paint { buffer, .. }
+            | Packet::SetCustomCursor { buffer, .. }
+            | Packet::SetIcon { buffer, .. }
+            | Packet::SetWallpaper { image: buffer, .. } => buffer.as_fd().into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
 
+    /// Moves received descriptors into the packet fields that expect them.
+    pub(crate) fn attach_fds(&mut self, fds: &mut VecDeque<OwnedFd>) -> Result<(), CodecError> {
+        let name = self.name();
+        if let Packet::Paint { buffer, .. }
+        | Packet::SetCustomCursor { buffer, .. }
+        | Packet::SetIcon { buffer, .. } = self
+        {
+            let fd = fds.pop_front().ok_or(CodecError::MissingFd(name))?;
+            *buffer = SharedFd::new(fd);
+        }
+        // The image is optional, so there may be nothing to take
+        if let Packet::SetWallpaper { image, .. } = self
+            && let Some(fd) = fds.pop_front()
+        {
+            *image = SharedFd::new(fd);
+        }
+        Ok(())
+    }
+}
 
 impl fmt::Display for Packet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Packet::Hello {
+                major,
+                minor,
+                patch,
+                capabilities,
+            } => write!(
+                f,
+                "Hello: version={}.{}.{}, capabilities={}",
+                major, minor, patch, capabilities
+            ),
             Packet::Create {
                 width,
                 height,
@@ -138,10 +667,16 @@ impl fmt::Display for Packet {
                 width, height, title
             ),
             Packet::Close { window_id } => write!(f, "Close: window_id={}", window_id),
-            Packet::Paint { window_id } => {
-                write!(f, "Paint: window_id={}", window_id)
-            }
-            Packet::RequestAPIVersion => write!(f, "RequestAPIVersion"),
+            Packet::Paint {
+                window_id,
+                width,
+                height,
+                ..
+            } => write!(
+                f,
+                "Paint: window_id={}, width={}, height={}",
+                window_id, width, height
+            ),
             Packet::CreateSuccess { window_id } => {
                 write!(f, "CreateSuccess: window_id={}", window_id)
             }
@@ -168,11 +703,132 @@ impl fmt::Display for Packet {
             Packet::Suspend => write!(f, "Suspend"),
             Packet::Resume => write!(f, "Resume"),
             Packet::DemandPaint => write!(f, "DemandPaint"),
-            Packet::APIVersion {
-                major,
-                minor,
-                patch,
-            } => write!(f, "APIVersion: {}.{}.{}", major, minor, patch),
+            Packet::Error {
+                request,
+                code,
+                message,
+            } => write!(f, "Error: request={}, code={}, message={}", request, code, message),
+            Packet::SetCursor { window_id, shape } => {
+                write!(f, "SetCursor: window_id={}, shape={}", window_id, shape)
+            }
+            Packet::SetCustomCursor {
+                window_id,
+                width,
+                height,
+                hotspot_x,
+                hotspot_y,
+                ..
+            } => write!(
+                f,
+                "SetCustomCursor: window_id={}, width={}, height={}, hotspot=({}, {})",
+                window_id, width, height, hotspot_x, hotspot_y
+            ),
+            Packet::SetWallpaper { mode, fill, image } => write!(
+                f,
+                "SetWallpaper: mode={}, fill={}, image={}",
+                mode,
+                fill,
+                image.as_fd().is_some()
+            ),
+            Packet::FocusIn => write!(f, "FocusIn"),
+            Packet::FocusOut => write!(f, "FocusOut"),
+            Packet::SetSizeLimits {
+                window_id,
+                min_width,
+                min_height,
+                max_width,
+                max_height,
+            } => write!(
+                f,
+                "SetSizeLimits: window_id={}, min={}x{}, max={:?}x{:?}",
+                window_id, min_width, min_height, max_width, max_height
+            ),
+            Packet::Maximize { window_id } => write!(f, "Maximize: window_id={}", window_id),
+            Packet::Unmaximize { window_id } => write!(f, "Unmaximize: window_id={}", window_id),
+            Packet::Minimize { window_id } => write!(f, "Minimize: window_id={}", window_id),
+            Packet::Fullscreen { window_id } => write!(f, "Fullscreen: window_id={}", window_id),
+            Packet::Unfullscreen { window_id } => {
+                write!(f, "Unfullscreen: window_id={}", window_id)
+            }
+            Packet::Configure {
+                serial,
+                width,
+                height,
+                state,
+            } => write!(
+                f,
+                "Configure: serial={}, width={}, height={}, state={}",
+                serial, width, height, state
+            ),
+            Packet::AckConfigure { window_id, serial } => {
+                write!(f, "AckConfigure: window_id={}, serial={}", window_id, serial)
+            }
+            Packet::SetTitle { window_id, title } => {
+                write!(f, "SetTitle: window_id={}, title={:?}", window_id, title)
+            }
+            Packet::SetAppId { window_id, app_id } => {
+                write!(f, "SetAppId: window_id={}, app_id={:?}", window_id, app_id)
+            }
+            Packet::SetIcon {
+                window_id,
+                width,
+                height,
+                ..
+            } => write!(
+                f,
+                "SetIcon: window_id={}, width={}, height={}",
+                window_id, width, height
+            ),
+            Packet::RemoveIcon { window_id } => write!(f, "RemoveIcon: window_id={}", window_id),
+            Packet::SetResizable {
+                window_id,
+                resizable,
+            } => write!(f, "SetResizable: window_id={}, resizable={}", window_id, resizable),
+            Packet::SetDecorations {
+                window_id,
+                decorated,
+            } => write!(f, "SetDecorations: window_id={}, decorated={}", window_id, decorated),
+            Packet::WindowEvent { window_id, event } => {
+                write!(f, "WindowEvent: window_id={}, event={}", window_id, Packet::from(*event))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::UnknownWindow => write!(f, "UnknownWindow"),
+            ErrorCode::NotOwner => write!(f, "NotOwner"),
+            ErrorCode::InvalidSize => write!(f, "InvalidSize"),
+            ErrorCode::InvalidBuffer => write!(f, "InvalidBuffer"),
+            ErrorCode::InvalidRequest => write!(f, "InvalidRequest"),
+            ErrorCode::IncompatibleVersion => write!(f, "IncompatibleVersion"),
+            ErrorCode::InvalidImage => write!(f, "InvalidImage"),
+        }
+    }
+}
+
+impl fmt::Display for CursorShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for WallpaperMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for BackgroundFill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = |[r, g, b]: [u8; 3]| format!("#{:02x}{:02x}{:02x}", r, g, b);
+        match self {
+            BackgroundFill::Solid(colour) => write!(f, "{}", hex(*colour)),
+            BackgroundFill::VerticalGradient { top, bottom } => {
+                write!(f, "{}:{}", hex(*top), hex(*bottom))
+            }
         }
     }
 }
@@ -198,6 +854,16 @@ impl fmt::Display for Modifiers {
     }
 }
 
+impl fmt::Display for WindowState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WindowState(maximized={}, minimized={}, fullscreen={})",
+            self.maximized, self.minimized, self.fullscreen
+        )
+    }
+}
+
 impl fmt::Display for KeyCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -212,3 +878,66 @@ impl fmt::Display for KeyCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(capabilities: Capabilities) -> Negotiated {
+        Negotiated {
+            minor: PROTOCOL_VERSION.1,
+            capabilities,
+        }
+    }
+
+    fn configure() -> Packet {
+        Packet::WindowEvent {
+            window_id: 7,
+            event: Event::Configure {
+                serial: 3,
+                width: 640,
+                height: 360,
+                state: WindowState::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn window_events_are_unwrapped_for_single_window_peers() {
+        let packet = Packet::WindowEvent {
+            window_id: 7,
+            event: Event::MouseEnter,
+        };
+        assert_eq!(packet.for_peer(&peer(Capabilities::INPUT_EVENTS)), Some(Packet::MouseEnter));
+    }
+
+    #[test]
+    fn window_events_stay_wrapped_for_multi_window_peers() {
+        let negotiated = peer(Capabilities::MULTI_WINDOW | Capabilities::WINDOW_STATES);
+        assert_eq!(configure().for_peer(&negotiated), Some(configure()));
+    }
+
+    #[test]
+    fn wrapped_configure_is_downgraded_to_resize() {
+        assert_eq!(
+            configure().for_peer(&peer(Capabilities::empty())),
+            Some(Packet::Resize { width: 640, height: 360 })
+        );
+        assert_eq!(
+            configure().for_peer(&peer(Capabilities::MULTI_WINDOW)),
+            Some(Packet::WindowEvent {
+                window_id: 7,
+                event: Event::Resize { width: 640, height: 360 },
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_window_events_are_dropped() {
+        let packet = Packet::WindowEvent {
+            window_id: 7,
+            event: Event::FocusIn,
+        };
+        assert_eq!(packet.for_peer(&peer(Capabilities::MULTI_WINDOW)), None);
+    }
+}
@@ -0,0 +1,182 @@
+//! Shared-memory pixel buffers.
+//!
+//! Clients draw into a [`ShmBuffer`] backed by an anonymous memfd and hand the
+//! descriptor to the compositor with [`Packet::Paint`](crate::Packet::Paint).
+//! The compositor maps the descriptor read-only with [`ShmMapping`] and keeps
+//! drawing the window from it until the next paint, so writes to the buffer
+//! may show up before they are painted. The memfd is sealed against shrinking so the compositor
+//! can never fault on a mapping the client truncated behind its back.
+
+use std::ffi::CStr;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::ptr::NonNull;
+
+/// Bytes per pixel of every buffer on the wire (8-bit RGBA, tightly packed).
+pub const BYTES_PER_PIXEL: usize = 4;
+
+const MEMFD_NAME: &CStr = c"prism-shm";
+
+struct Mapping {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: BorrowedFd<'_>, len: usize, prot: libc::c_int) -> io::Result<Self> {
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty buffer",
+            ));
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).expect("mmap returned null"),
+            len,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
+
+// The mapping is plain memory owned by this process; it is only reachable
+// through the owning buffer so moving it across threads is fine.
+unsafe impl Send for Mapping {}
+
+/// A writable, memfd-backed pixel buffer owned by a client.
+pub struct ShmBuffer {
+    fd: OwnedFd,
+    mapping: Mapping,
+}
+
+impl ShmBuffer {
+    /// Creates a new buffer of `len` bytes, sealed against shrinking.
+    pub fn create(len: usize) -> io::Result<Self> {
+        let raw = unsafe {
+            libc::memfd_create(
+                MEMFD_NAME.as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+        truncate(fd.as_fd(), len)?;
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, libc::F_SEAL_SHRINK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mapping = Mapping::new(fd.as_fd(), len, libc::PROT_READ | libc::PROT_WRITE)?;
+        Ok(Self { fd, mapping })
+    }
+
+    /// Grows the buffer to at least `len` bytes. Existing contents are kept.
+    pub fn ensure_len(&mut self, len: usize) -> io::Result<()> {
+        if len <= self.mapping.len {
+            return Ok(());
+        }
+        truncate(self.fd.as_fd(), len)?;
+        self.mapping = Mapping::new(self.fd.as_fd(), len, libc::PROT_READ | libc::PROT_WRITE)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.mapping.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mapping.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.mapping.ptr.as_ptr(), self.mapping.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.mapping.ptr.as_ptr(), self.mapping.len) }
+    }
+
+    /// Duplicates the descriptor so it can be attached to a packet.
+    pub fn share(&self) -> io::Result<OwnedFd> {
+        self.fd.try_clone()
+    }
+}
+
+impl AsFd for ShmBuffer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// A read-only view of a buffer received from a client.
+pub struct ShmMapping {
+    mapping: Mapping,
+}
+
+impl ShmMapping {
+    /// Maps the first `len` bytes of `fd`.
+    ///
+    /// Fails unless the descriptor is at least `len` bytes long and sealed
+    /// against shrinking, so reads through the mapping cannot raise `SIGBUS`.
+    pub fn map(fd: BorrowedFd<'_>, len: usize) -> io::Result<Self> {
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if seals & libc::F_SEAL_SHRINK == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "shared buffer is not sealed against shrinking",
+            ));
+        }
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if (stat.st_size as u64) < len as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "shared buffer holds {} bytes but {} were requested",
+                    stat.st_size, len
+                ),
+            ));
+        }
+        Ok(Self {
+            mapping: Mapping::new(fd, len, libc::PROT_READ)?,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.mapping.ptr.as_ptr(), self.mapping.len) }
+    }
+}
+
+// The mapping is read-only, so sharing it between threads only ever reads it.
+unsafe impl Sync for ShmMapping {}
+
+fn truncate(fd: BorrowedFd<'_>, len: usize) -> io::Result<()> {
+    if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Where the compositor socket lives.
+//!
+//! The socket is `$XDG_RUNTIME_DIR/$PRISM_DISPLAY`, falling back to
+//! [`DEFAULT_DISPLAY`] when `PRISM_DISPLAY` is unset. An absolute
+//! `PRISM_DISPLAY` is used as-is. The runtime directory belongs to the user,
+//! so other users cannot reach the socket at all.
+//!
+//! Next to it sits the control socket, `<socket>.control`, for desktop
+//! settings tools rather than applications. Only connections made through it
+//! are offered [`CONTROL_CAPABILITIES`](crate::CONTROL_CAPABILITIES).
+
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the display to serve or connect to.
+pub const DISPLAY_ENV: &str = "PRISM_DISPLAY";
+/// Display used when `PRISM_DISPLAY` is not set.
+pub const DEFAULT_DISPLAY: &str = "prism-0";
+
+/// Resolves the compositor socket for the current user and display.
+pub fn socket_path() -> io::Result<PathBuf> {
+    resolve_socket_path(env::var_os("XDG_RUNTIME_DIR"), env::var_os(DISPLAY_ENV))
+}
+
+fn resolve_socket_path(runtime_dir: Option<OsString>, display: Option<OsString>) -> io::Result<PathBuf> {
+    let runtime_dir = runtime_dir
+        .filter(|dir| !dir.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?;
+    let display = display
+        .filter(|display| !display.is_empty())
+        .unwrap_or_else(|| DEFAULT_DISPLAY.into());
+    Ok(PathBuf::from(runtime_dir).join(display))
+}
+
+/// Control socket of the compositor serving `socket`.
+pub fn control_socket_path(socket: &Path) -> PathBuf {
+    let mut path = OsString::from(socket.as_os_str());
+    path.push(".control");
+    PathBuf::from(path)
+}
+
+/// Lock file that keeps a second compositor off `socket`.
+pub fn lock_path(socket: &Path) -> PathBuf {
+    let mut path = OsString::from(socket.as_os_str());
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(runtime_dir: Option<&str>, display: Option<&str>) -> io::Result<PathBuf> {
+        resolve_socket_path(runtime_dir.map(OsString::from), display.map(OsString::from))
+    }
+
+    #[test]
+    fn missing_runtime_dir_is_an_error() {
+        for runtime_dir in [None, Some("")] {
+            let error = resolve(runtime_dir, Some("prism-1")).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        }
+    }
+
+    #[test]
+    fn display_defaults_when_unset() {
+        for display in [None, Some("")] {
+            let path = resolve(Some("/run/user/1000"), display).unwrap();
+            assert_eq!(path, Path::new("/run/user/1000").join(DEFAULT_DISPLAY));
+        }
+    }
+
+    #[test]
+    fn relative_display_is_under_runtime_dir() {
+        let path = resolve(Some("/run/user/1000"), Some("prism-1")).unwrap();
+        assert_eq!(path, Path::new("/run/user/1000/prism-1"));
+    }
+
+    #[test]
+    fn absolute_display_is_used_as_is() {
+        let path = resolve(Some("/run/user/1000"), Some("/tmp/prism-test")).unwrap();
+        assert_eq!(path, Path::new("/tmp/prism-test"));
+    }
+
+    #[test]
+    fn control_and_lock_paths_sit_next_to_socket() {
+        let socket = Path::new("/run/user/1000/prism-0");
+        assert_eq!(control_socket_path(socket), Path::new("/run/user/1000/prism-0.control"));
+        assert_eq!(lock_path(socket), Path::new("/run/user/1000/prism-0.lock"));
+    }
+}
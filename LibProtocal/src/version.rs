@@ -0,0 +1,177 @@
+//! Version and capability negotiation.
+//!
+//! The first packet on every connection is a [`Packet::Hello`](crate::Packet::Hello)
+//! from the client, answered by one from the compositor. Each side sends its
+//! [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) and the capabilities it
+//! implements. Major versions have to match; minor versions are downward
+//! compatible, so the connection speaks the lower of the two. Optional
+//! features are gated on the capabilities both sides advertised rather than
+//! on version numbers.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::BitOr;
+
+/// A set of optional protocol features.
+///
+/// Bits a peer does not know about are dropped during negotiation, so new
+/// flags can be added without a major version bump.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Window contents arrive as shared-memory buffers in `Paint`.
+    pub const SHM_BUFFERS: Self = Self(1 << 0);
+    /// The compositor forwards pointer and keyboard input.
+    pub const INPUT_EVENTS: Self = Self(1 << 1);
+    /// Rejected requests are answered with `Packet::Error`.
+    pub const ERROR_REPLIES: Self = Self(1 << 2);
+    /// Clients can set the cursor shown over their windows.
+    pub const CURSORS: Self = Self(1 << 3);
+    /// Clients can change the desktop wallpaper. Only offered on the
+    /// control socket.
+    pub const WALLPAPER: Self = Self(1 << 4);
+    /// The compositor tells clients when their window gains or loses focus.
+    pub const FOCUS_EVENTS: Self = Self(1 << 5);
+    /// Clients can limit how far their windows are resized.
+    pub const SIZE_LIMITS: Self = Self(1 << 6);
+    /// Clients can maximize, minimize and fullscreen their windows and are
+    /// told about state changes with `Packet::Configure`.
+    pub const WINDOW_STATES: Self = Self(1 << 7);
+    /// Clients acknowledge `Packet::Configure` before painting at a new
+    /// size, so resizes never show a frame at the wrong size.
+    pub const CONFIGURE_ACK: Self = Self(1 << 8);
+    /// Clients can change their window's title, app ID, icon, resizability
+    /// and decorations after creating it.
+    pub const WINDOW_PROPERTIES: Self = Self(1 << 9);
+    /// Clients can own several windows, and events name the window they
+    /// are for with `Packet::WindowEvent`.
+    pub const MULTI_WINDOW: Self = Self(1 << 10);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// Everything this build of the protocol implements.
+pub const PROTOCOL_CAPABILITIES: Capabilities = Capabilities(
+    Capabilities::SHM_BUFFERS.0
+        | Capabilities::INPUT_EVENTS.0
+        | Capabilities::ERROR_REPLIES.0
+        | Capabilities::CURSORS.0
+        | Capabilities::WALLPAPER.0
+        | Capabilities::FOCUS_EVENTS.0
+        | Capabilities::SIZE_LIMITS.0
+        | Capabilities::WINDOW_STATES.0
+        | Capabilities::CONFIGURE_ACK.0
+        | Capabilities::WINDOW_PROPERTIES.0
+        | Capabilities::MULTI_WINDOW.0,
+);
+
+/// Features the compositor offers only on its control socket.
+pub const CONTROL_CAPABILITIES: Capabilities = Capabilities::WALLPAPER;
+
+/// What both ends of a connection agreed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Minor version the connection speaks.
+    pub minor: u8,
+    /// Features both sides support.
+    pub capabilities: Capabilities,
+}
+
+impl Negotiated {
+    pub fn supports(&self, capabilities: Capabilities) -> bool {
+        self.capabilities.contains(capabilities)
+    }
+}
+
+/// Checks a peer's `Hello` against this build.
+///
+/// Returns `None` when the major versions differ.
+pub fn negotiate(major: u8, minor: u8, capabilities: Capabilities) -> Option<Negotiated> {
+    let (our_major, our_minor, _) = crate::PROTOCOL_VERSION;
+    if major != our_major {
+        return None;
+    }
+    Some(Negotiated {
+        minor: minor.min(our_minor),
+        capabilities: capabilities.intersection(PROTOCOL_CAPABILITIES),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PROTOCOL_VERSION;
+
+    #[test]
+    fn different_major_is_refused() {
+        let (major, minor, _) = PROTOCOL_VERSION;
+        assert_eq!(negotiate(major + 1, minor, PROTOCOL_CAPABILITIES), None);
+        if let Some(older) = major.checked_sub(1) {
+            assert_eq!(negotiate(older, minor, PROTOCOL_CAPABILITIES), None);
+        }
+    }
+
+    #[test]
+    fn newer_minor_falls_back_to_ours() {
+        let (major, minor, _) = PROTOCOL_VERSION;
+        let negotiated = negotiate(major, minor + 1, PROTOCOL_CAPABILITIES).unwrap();
+        assert_eq!(negotiated.minor, minor);
+    }
+
+    #[test]
+    fn older_minor_is_kept() {
+        let (major, minor, _) = PROTOCOL_VERSION;
+        let older = minor.saturating_sub(1);
+        assert_eq!(negotiate(major, older, PROTOCOL_CAPABILITIES).unwrap().minor, older);
+    }
+
+    #[test]
+    fn capabilities_are_intersected() {
+        let (major, minor, _) = PROTOCOL_VERSION;
+        let peer = Capabilities::SHM_BUFFERS | Capabilities::CURSORS;
+        assert_eq!(negotiate(major, minor, peer).unwrap().capabilities, peer);
+        // Bits this build does not know about are dropped
+        let unknown = Capabilities(1 << 31);
+        let negotiated = negotiate(major, minor, peer | unknown).unwrap();
+        assert_eq!(negotiated.capabilities, peer);
+        assert!(!negotiated.supports(unknown));
+        assert!(!negotiated.supports(Capabilities::INPUT_EVENTS));
+    }
+}
@@ -1,5 +1,5 @@
 // Fragment shader
-#version 460 core
+#version 450 core
 
 layout (location = 0) in VS_OUT {
     vec2 TexCoords;
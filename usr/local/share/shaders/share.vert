@@ -1,4 +1,4 @@
-#version 460 core
+#version 450 core
 layout (location = 0) uniform mat4 model;
 layout (location = 1) uniform mat4 view;
 layout (location = 2) uniform mat4 proj;
@@ -0,0 +1,13 @@
+#version 450 core
+layout (location = 0) in VS_OUT {
+    vec2 TexCoords;
+} fs_in;
+layout (location = 0) out vec4 FragColor;
+
+layout (location = 3) uniform sampler2D atlas;
+layout (location = 4) uniform vec4 uColor;      // Colour of the text
+layout (location = 5) uniform vec4 uUvRect;     // Glyph in the atlas: offset, size
+void main() {
+    vec2 uv = uUvRect.xy + fs_in.TexCoords * uUvRect.zw;
+    FragColor = vec4(uColor.rgb, uColor.a * texture(atlas, uv).a);
+}